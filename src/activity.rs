@@ -0,0 +1,151 @@
+use benimator::Play;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::input::PlayerTag;
+use crate::simple_figure::SimpleFigureTag;
+
+/// Freezes NPCs that are far from every player, so large maps don't spend
+/// time animating and simulating characters nobody can see
+pub struct ActivityPlugin;
+
+impl Plugin for ActivityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActivitySettings>()
+            .add_system(update_activity.before("movement"));
+    }
+}
+
+pub struct ActivitySettings {
+    /// NPCs further than this from every player go dormant
+    pub sleep_radius: f32, // m
+    /// Dormant NPCs closer than this to any player wake up. Smaller than
+    /// sleep_radius, so NPCs on the edge don't flicker between the two.
+    pub wake_radius: f32, // m
+}
+
+impl Default for ActivitySettings {
+    fn default() -> Self {
+        ActivitySettings {
+            sleep_radius: 25.0,
+            wake_radius: 20.0,
+        }
+    }
+}
+
+/// An NPC frozen in time because no player is near. It doesn't animate,
+/// move, dodge, take hazard damage or plan paths.
+#[derive(Component)]
+pub struct Dormant;
+
+fn update_activity(
+    mut commands: Commands,
+    settings: Res<ActivitySettings>,
+    players: Query<&RigidBodyPositionComponent, With<PlayerTag>>,
+    mut npcs: Query<
+        (
+            Entity,
+            &RigidBodyPositionComponent,
+            &mut RigidBodyVelocityComponent,
+            &mut RigidBodyActivationComponent,
+            Option<&Dormant>,
+        ),
+        (With<SimpleFigureTag>, Without<PlayerTag>),
+    >,
+) {
+    let player_positions: Vec<Vec2> = players
+        .iter()
+        .map(|position| position.position.translation.into())
+        .collect();
+    // Without a player there is nobody to be near, so leave everything as is
+    if player_positions.is_empty() {
+        return;
+    }
+
+    for (entity, position, mut velocity, mut activation, dormant) in npcs.iter_mut() {
+        let npc_position: Vec2 = position.position.translation.into();
+        let nearest = player_positions
+            .iter()
+            .map(|player_position| player_position.distance(npc_position))
+            .fold(f32::INFINITY, f32::min);
+
+        match dormant {
+            None if nearest > settings.sleep_radius => {
+                velocity.linvel = Vector::zeros();
+                activation.sleep();
+                commands.entity(entity).insert(Dormant).remove::<Play>();
+            }
+            Some(_) if nearest < settings.wake_radius => {
+                activation.wake_up(true);
+                commands.entity(entity).remove::<Dormant>().insert(Play);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<ActivitySettings>()
+            .add_system(update_activity);
+        app
+    }
+
+    fn spawn_body(app: &mut App, x: f32) -> Entity {
+        let position: RigidBodyPositionComponent = Isometry::translation(x, 0.0).into();
+        let velocity: RigidBodyVelocityComponent = RigidBodyVelocity {
+            linvel: Vector::new(1.0, 0.0),
+            angvel: 0.0,
+        }
+        .into();
+        let activation: RigidBodyActivationComponent = RigidBodyActivation::default().into();
+        app.world
+            .spawn()
+            .insert(SimpleFigureTag)
+            .insert(position)
+            .insert(velocity)
+            .insert(activation)
+            .insert(Play)
+            .id()
+    }
+
+    #[test]
+    fn far_npcs_freeze_until_a_player_comes_near() {
+        let mut app = activity_app();
+        let player = spawn_body(&mut app, 0.0);
+        app.world.entity_mut(player).insert(PlayerTag);
+        let npc = spawn_body(&mut app, 100.0);
+
+        for _ in 0..10 {
+            app.update();
+        }
+        assert!(app.world.get::<Dormant>(npc).is_some());
+        assert!(app.world.get::<Play>(npc).is_none());
+        let velocity = app.world.get::<RigidBodyVelocityComponent>(npc).unwrap();
+        assert_eq!(velocity.linvel, Vector::zeros());
+        // The player itself never goes dormant
+        assert!(app.world.get::<Dormant>(player).is_none());
+
+        // Between the wake and sleep radii nothing changes
+        let mut position = app
+            .world
+            .get_mut::<RigidBodyPositionComponent>(player)
+            .unwrap();
+        position.position = Isometry::translation(78.0, 0.0);
+        app.update();
+        assert!(app.world.get::<Dormant>(npc).is_some());
+
+        let mut position = app
+            .world
+            .get_mut::<RigidBodyPositionComponent>(player)
+            .unwrap();
+        position.position = Isometry::translation(90.0, 0.0);
+        app.update();
+        assert!(app.world.get::<Dormant>(npc).is_none());
+        assert!(app.world.get::<Play>(npc).is_some());
+    }
+}
@@ -1,40 +1,825 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::input::PlayerTag;
+use crate::activity::Dormant;
+use crate::aim::line_of_sight;
+use crate::ball::{BallSpawnEvent, BallTag, ProjectileKind};
+use crate::health::{CollisionDamage, Health};
+use crate::input::{MoveAction, PlayerTag};
 use crate::pathfinding::GoalPosition;
+use crate::pathfollowing::Carrot;
 use crate::simple_figure::SimpleFigureTag;
+use crate::tuning::Tuning;
 
 pub struct AiPlugin;
 
 impl Plugin for AiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup).add_system(zombie_follow);
+        app.init_resource::<DifficultyCurve>()
+            .init_resource::<Difficulty>()
+            .init_resource::<DodgeSettings>()
+            .add_startup_system(setup)
+            .add_system(advance_difficulty)
+            .add_system(cycle_difficulty)
+            .add_system(apply_difficulty_change)
+            .add_system(zombie_follow.label("zombie_follow"))
+            // Reads the replan timer zombie_follow ticks
+            .add_system(friendly_follow.after("zombie_follow"))
+            .add_system(friendly_shoot)
+            .add_system(dodge_balls.after("go_to_carrot").before("movement"));
+    }
+}
+
+/// Which side a character fights on. Players are always Friendly.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Allegiance {
+    Hostile,
+    Friendly,
+    Neutral,
+}
+
+impl Default for Allegiance {
+    fn default() -> Self {
+        Allegiance::Hostile
+    }
+}
+
+/// Stats of hostile NPCs, relative to the base zombie
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NpcTuning {
+    pub speed_mult: f32,
+    pub health_mult: f32,
+    pub damage_mult: f32,
+    /// Multiplies the tuned time between path replans
+    pub replan_mult: f32,
+    /// Hostiles ignore targets further away than this
+    pub sight_range: f32, // m
+    /// Whether hostiles dodge incoming balls
+    pub dodge: bool,
+}
+
+impl NpcTuning {
+    /// Check for values that would break hostiles, like a negative replan
+    /// time
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("speed_mult", self.speed_mult),
+            ("health_mult", self.health_mult),
+            ("damage_mult", self.damage_mult),
+            ("sight_range", self.sight_range),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("{} can't be {}", name, value));
+            }
+        }
+        if !self.replan_mult.is_finite() || self.replan_mult <= 0.0 {
+            return Err(format!("replan_mult can't be {}", self.replan_mult));
+        }
+        Ok(())
+    }
+}
+
+/// Difficulty preset for hostile NPCs, cycled with F6
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Custom(NpcTuning),
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    pub fn tuning(&self) -> NpcTuning {
+        match self {
+            Difficulty::Easy => NpcTuning {
+                speed_mult: 0.8,
+                health_mult: 0.6,
+                damage_mult: 1.0,
+                replan_mult: 2.0,
+                sight_range: 8.0,
+                dodge: false,
+            },
+            Difficulty::Normal => NpcTuning {
+                speed_mult: 1.0,
+                health_mult: 1.0,
+                damage_mult: 1.0,
+                replan_mult: 1.0,
+                sight_range: 30.0,
+                dodge: true,
+            },
+            Difficulty::Hard => NpcTuning {
+                speed_mult: 1.3,
+                health_mult: 1.5,
+                damage_mult: 2.0,
+                replan_mult: 0.6,
+                sight_range: 30.0,
+                dodge: true,
+            },
+            Difficulty::Custom(tuning) => *tuning,
+        }
+    }
+
+    /// The next preset. Custom tuning is only set from code, so it cycles
+    /// back to Easy.
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard | Difficulty::Custom(_) => Difficulty::Easy,
+        }
+    }
+}
+
+const BASE_NPC_HEALTH: i32 = 5;
+const BASE_NPC_DAMAGE: i32 = 1;
+
+/// Health and contact damage of a freshly spawned NPC
+pub fn npc_stats(allegiance: Allegiance, tuning: &NpcTuning) -> (Health, Option<CollisionDamage>) {
+    if allegiance != Allegiance::Hostile {
+        return (Health::from_max(BASE_NPC_HEALTH), None);
+    }
+    let health = Health::from_max(scale_stat(BASE_NPC_HEALTH, tuning.health_mult));
+    let damage = CollisionDamage {
+        damage: scale_stat(BASE_NPC_DAMAGE, tuning.damage_mult),
+    };
+    (health, Some(damage))
+}
+
+/// Scale an integer stat, never dropping it below 1
+fn scale_stat(base: i32, mult: f32) -> i32 {
+    ((base as f32 * mult).round() as i32).max(1)
+}
+
+fn cycle_difficulty(keyboard_input: Res<Input<KeyCode>>, mut difficulty: ResMut<Difficulty>) {
+    if keyboard_input.just_pressed(KeyCode::F6) {
+        *difficulty = difficulty.next();
+        info!("Difficulty: {:?}", *difficulty);
+    }
+}
+
+/// Give hostiles that are already alive the stats of the new difficulty when
+/// it changes mid-session, keeping the fraction of health they have left.
+/// Speed and replan rate are read live.
+fn apply_difficulty_change(
+    difficulty: Res<Difficulty>,
+    mut previous: Local<Option<NpcTuning>>,
+    mut hostiles: Query<(&Allegiance, &mut Health, Option<&mut CollisionDamage>), Without<BallTag>>,
+) {
+    let tuning = difficulty.tuning();
+    if previous
+        .replace(tuning)
+        .map_or(true, |previous| previous == tuning)
+    {
+        return;
+    }
+
+    // Recomputed from the base stats, so switching back and forth doesn't
+    // compound rounding
+    let (new_health, new_damage) = npc_stats(Allegiance::Hostile, &tuning);
+    for (allegiance, mut health, damage) in hostiles.iter_mut() {
+        if *allegiance != Allegiance::Hostile {
+            continue;
+        }
+        let fraction = health.current as f32 / health.max as f32;
+        health.max = new_health.max;
+        health.current = ((fraction * health.max as f32).round() as i32).clamp(1, health.max);
+        if let (Some(mut damage), Some(new_damage)) = (damage, new_damage) {
+            damage.damage = new_damage.damage;
+        }
     }
 }
 
+/// Escalates hostile NPC speed and aggression the longer a session runs
+pub struct DifficultyCurve {
+    /// Seconds since the session started
+    pub elapsed: f32,
+    /// How much the factor grows per minute
+    pub ramp_per_minute: f32,
+    /// Upper bound of the factor
+    pub max_factor: f32,
+}
+
+impl Default for DifficultyCurve {
+    fn default() -> Self {
+        DifficultyCurve {
+            elapsed: 0.0,
+            ramp_per_minute: 0.1,
+            max_factor: 2.0,
+        }
+    }
+}
+
+impl DifficultyCurve {
+    /// Multiplier for hostile speed and replan rate, starting at 1
+    pub fn factor(&self) -> f32 {
+        (1.0 + self.ramp_per_minute * self.elapsed / 60.0).min(self.max_factor)
+    }
+}
+
+fn advance_difficulty(time: Res<Time>, mut curve: ResMut<DifficultyCurve>) {
+    curve.elapsed += time.delta_seconds();
+}
+
 struct ReplanTimer(Timer);
 
-fn setup(mut commands: Commands) {
-    commands.insert_resource(ReplanTimer(Timer::from_seconds(0.5, true)));
+fn setup(mut commands: Commands, difficulty: Res<Difficulty>, tuning: Res<Tuning>) {
+    commands.insert_resource(ReplanTimer(Timer::from_seconds(
+        tuning.ai.replan_interval * difficulty.tuning().replan_mult,
+        true,
+    )));
 }
 
+/// Hostiles chase whichever player or friendly is nearest. Dormant ones don't
+/// replan until they wake up.
 fn zombie_follow(
     mut commands: Commands,
     time: Res<Time>,
     mut timer: ResMut<ReplanTimer>,
-    player: Query<&RigidBodyPositionComponent, With<PlayerTag>>,
-    zombies: Query<Entity, (Without<PlayerTag>, With<SimpleFigureTag>)>,
+    curve: Res<DifficultyCurve>,
+    difficulty: Res<Difficulty>,
+    tuning: Res<Tuning>,
+    characters: Query<(Entity, &RigidBodyPositionComponent, &Allegiance), With<SimpleFigureTag>>,
+    awake: Query<
+        (Entity, &RigidBodyPositionComponent, &Allegiance),
+        (With<SimpleFigureTag>, Without<Dormant>),
+    >,
 ) {
+    let npc_tuning = difficulty.tuning();
+    // Hostiles replan more often as the difficulty ramps up
+    let interval = Duration::from_secs_f32(
+        tuning.ai.replan_interval * npc_tuning.replan_mult / curve.factor(),
+    );
+    if timer.0.duration() != interval {
+        timer.0.set_duration(interval);
+    }
     timer.0.tick(time.delta());
     if timer.0.finished() {
-        if let Some(player_position) = player.iter().next() {
-            for entity in zombies.iter() {
+        for (entity, zombie_position, allegiance) in awake.iter() {
+            if *allegiance != Allegiance::Hostile {
+                continue;
+            }
+            let zombie_translation: Vec2 = zombie_position.position.translation.into();
+            let targets = characters
+                .iter()
+                .filter(|(_, _, allegiance)| **allegiance == Allegiance::Friendly)
+                .map(|(_, position, _)| -> (Isometry<Real>, Vec2) {
+                    (position.position, position.position.translation.into())
+                })
+                .filter(|(_, translation)| {
+                    translation.distance(zombie_translation) <= npc_tuning.sight_range
+                });
+            if let Some((target_position, _)) = nearest(zombie_translation, targets) {
                 info!("Resetting zombie goal");
                 commands.entity(entity).insert(GoalPosition {
-                    position: player_position.position,
+                    position: target_position,
+                });
+            }
+        }
+    }
+}
+
+/// Friendly NPCs trail the nearest player
+fn friendly_follow(
+    mut commands: Commands,
+    timer: Res<ReplanTimer>,
+    tuning: Res<Tuning>,
+    players: Query<&RigidBodyPositionComponent, With<PlayerTag>>,
+    friendlies: Query<
+        (Entity, &RigidBodyPositionComponent, &Allegiance),
+        (Without<PlayerTag>, Without<Dormant>),
+    >,
+) {
+    if !timer.0.finished() {
+        return;
+    }
+    for (entity, friendly_position, allegiance) in friendlies.iter() {
+        if *allegiance != Allegiance::Friendly {
+            continue;
+        }
+        let friendly_translation: Vec2 = friendly_position.position.translation.into();
+        let players = players
+            .iter()
+            .map(|position| ((), position.position.translation.into()));
+        if let Some((_, player_translation)) = nearest(friendly_translation, players) {
+            if let Some(goal) = follow_goal(
+                friendly_translation,
+                player_translation,
+                tuning.ai.follow_distance,
+            ) {
+                commands.entity(entity).insert(GoalPosition {
+                    position: Isometry::new(goal.into(), 0.0),
                 });
             }
         }
     }
 }
+
+/// The candidate closest to origin. Candidates with NaN positions sort last
+/// rather than panicking.
+fn nearest<T>(origin: Vec2, candidates: impl Iterator<Item = (T, Vec2)>) -> Option<(T, Vec2)> {
+    candidates
+        .map(|(candidate, position)| {
+            let distance = position.distance_squared(origin);
+            let distance = if distance.is_nan() {
+                f32::INFINITY
+            } else {
+                distance
+            };
+            (candidate, position, distance)
+        })
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+        .map(|(candidate, position, _)| (candidate, position))
+}
+
+/// Where a friendly should go to trail a player by follow_distance, or None
+/// if it is already close enough
+fn follow_goal(friendly: Vec2, player: Vec2, follow_distance: f32) -> Option<Vec2> {
+    if player.distance(friendly) <= follow_distance {
+        return None;
+    }
+    let offset = (friendly - player).normalize_or_zero();
+    Some(player + offset * follow_distance)
+}
+
+/// Time until a character can shoot again
+#[derive(Component)]
+pub struct ShootCooldown(pub Timer);
+
+fn friendly_shoot(
+    mut commands: Commands,
+    time: Res<Time>,
+    tuning: Res<Tuning>,
+    characters: Query<(Entity, &RigidBodyPositionComponent, &Allegiance), Without<PlayerTag>>,
+    mut cooldowns: Query<&mut ShootCooldown>,
+    dormant: Query<(), With<Dormant>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut ball_spawn_event: EventWriter<BallSpawnEvent>,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (entity, position, allegiance) in characters.iter() {
+        if *allegiance != Allegiance::Friendly || dormant.get(entity).is_ok() {
+            continue;
+        }
+
+        if let Ok(mut cooldown) = cooldowns.get_mut(entity) {
+            if !cooldown.0.tick(time.delta()).finished() {
+                continue;
+            }
+        }
+
+        let origin: Vec2 = position.position.translation.into();
+        let targets = characters
+            .iter()
+            .filter(|(_, _, allegiance)| **allegiance == Allegiance::Hostile)
+            .map(|(target, position, _)| -> (Entity, Vec2) {
+                (target, position.position.translation.into())
+            })
+            .filter(|(_, target_position)| {
+                target_position.distance(origin) <= tuning.ai.friendly_sight_range
+            })
+            .filter(|(target, target_position)| {
+                line_of_sight(
+                    &query_pipeline,
+                    &collider_set,
+                    entity,
+                    origin,
+                    *target,
+                    *target_position,
+                )
+            });
+        let target = nearest(origin, targets);
+
+        if let Some((_, target_position)) = target {
+            let direction = (target_position - origin).normalize_or_zero();
+            ball_spawn_event.send(BallSpawnEvent::shot(
+                origin,
+                direction,
+                tuning.combat.shot_speed,
+                ProjectileKind::default(),
+                entity,
+            ));
+            commands
+                .entity(entity)
+                .insert(ShootCooldown(Timer::from_seconds(
+                    tuning.ai.friendly_shoot_interval,
+                    false,
+                )));
+        }
+    }
+}
+
+/// Closest point of approach of two points moving at constant velocity.
+/// Returns the time until it happens (never negative) and the distance
+/// between the points at that time.
+pub fn closest_approach(relative_position: Vec2, relative_velocity: Vec2) -> (f32, f32) {
+    let speed_squared = relative_velocity.length_squared();
+    let time = if speed_squared == 0.0 {
+        0.0
+    } else {
+        (-relative_position.dot(relative_velocity) / speed_squared).max(0.0)
+    };
+    (
+        time,
+        (relative_position + relative_velocity * time).length(),
+    )
+}
+
+/// How hostile NPCs react to incoming balls. Whether they do at all depends
+/// on the difficulty.
+pub struct DodgeSettings {
+    /// Balls further away than this are ignored
+    pub awareness_radius: f32, // m
+    /// Balls predicted to pass closer than this are dodged
+    pub danger_distance: f32, // m
+    /// Below this fraction of max health, flee instead of sidestepping
+    pub flee_health_fraction: f32,
+}
+
+impl Default for DodgeSettings {
+    fn default() -> Self {
+        DodgeSettings {
+            awareness_radius: 4.0,
+            danger_distance: 0.6,
+            flee_health_fraction: 0.4,
+        }
+    }
+}
+
+const DODGE_SPEED: f32 = 1.0; // MoveAction units
+const DODGE_DURATION: f32 = 0.3; // seconds
+const FLEE_DURATION: f32 = 1.0; // seconds
+
+/// Velocity that replaces an NPC's MoveAction while it gets out of the way
+#[derive(Component)]
+pub struct Dodge {
+    timer: Timer,
+    velocity: Vec2,
+}
+
+fn dodge_balls(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<DodgeSettings>,
+    difficulty: Res<Difficulty>,
+    balls: Query<(&RigidBodyPositionComponent, &RigidBodyVelocityComponent), With<BallTag>>,
+    mut npcs: Query<
+        (
+            Entity,
+            &RigidBodyPositionComponent,
+            &Allegiance,
+            &mut MoveAction,
+            Option<&Health>,
+            Option<&mut Dodge>,
+            Option<&mut Carrot>,
+        ),
+        (Without<BallTag>, Without<Dormant>),
+    >,
+) {
+    for (entity, position, allegiance, mut move_action, health, dodge, carrot) in npcs.iter_mut() {
+        if *allegiance != Allegiance::Hostile {
+            continue;
+        }
+
+        if let Some(mut dodge) = dodge {
+            if dodge.timer.tick(time.delta()).finished() {
+                commands.entity(entity).remove::<Dodge>();
+                // Let path following pick its velocity back up
+                match carrot {
+                    Some(mut carrot) => carrot.set_changed(),
+                    None => move_action.desired_velocity = Vec2::ZERO,
+                }
+            } else {
+                // Override path following, which may have written a new
+                // velocity this frame
+                move_action.desired_velocity = dodge.velocity;
+            }
+            continue;
+        }
+
+        if !difficulty.tuning().dodge {
+            continue;
+        }
+
+        let npc_position: Vec2 = position.position.translation.into();
+        for (ball_position, ball_velocity) in balls.iter() {
+            let ball_position: Vec2 = ball_position.position.translation.into();
+            let ball_velocity: Vec2 = ball_velocity.linvel.into();
+            let relative_position = ball_position - npc_position;
+            if relative_position.length() > settings.awareness_radius {
+                continue;
+            }
+
+            let (time_to_approach, miss_distance) =
+                closest_approach(relative_position, ball_velocity);
+            if time_to_approach <= 0.0 || miss_distance > settings.danger_distance {
+                continue;
+            }
+
+            let low_health = health.map_or(false, |health| {
+                (health.current as f32) < settings.flee_health_fraction * health.max as f32
+            });
+            let (velocity, duration) = if low_health {
+                (-relative_position.normalize_or_zero(), FLEE_DURATION)
+            } else {
+                // Step to whichever side of the ball's path we are already on
+                let perpendicular = ball_velocity.perp().normalize_or_zero();
+                let side = if perpendicular.dot(-relative_position) >= 0.0 {
+                    1.0
+                } else {
+                    -1.0
+                };
+                (perpendicular * side, DODGE_DURATION)
+            };
+            move_action.desired_velocity = velocity * DODGE_SPEED;
+            commands.entity(entity).insert(Dodge {
+                timer: Timer::from_seconds(duration, false),
+                velocity: velocity * DODGE_SPEED,
+            });
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::AiTuning;
+
+    #[test]
+    fn npc_stats_follow_the_preset() {
+        let (health, damage) = npc_stats(Allegiance::Hostile, &Difficulty::Easy.tuning());
+        assert_eq!((health.max, health.current), (3, 3));
+        assert_eq!(damage.unwrap().damage, 1);
+
+        let (health, damage) = npc_stats(Allegiance::Hostile, &Difficulty::Normal.tuning());
+        assert_eq!(health.max, BASE_NPC_HEALTH);
+        assert_eq!(damage.unwrap().damage, BASE_NPC_DAMAGE);
+
+        let (health, damage) = npc_stats(Allegiance::Hostile, &Difficulty::Hard.tuning());
+        assert_eq!(health.max, 8);
+        assert_eq!(damage.unwrap().damage, 2);
+
+        // Only hostiles are scaled
+        let (health, damage) = npc_stats(Allegiance::Friendly, &Difficulty::Hard.tuning());
+        assert_eq!(health.max, BASE_NPC_HEALTH);
+        assert!(damage.is_none());
+    }
+
+    #[test]
+    fn scaled_stats_never_drop_below_one() {
+        assert_eq!(scale_stat(5, 0.01), 1);
+        assert_eq!(scale_stat(1, 0.4), 1);
+        assert_eq!(scale_stat(4, 1.5), 6);
+    }
+
+    #[test]
+    fn presets_cycle() {
+        assert_eq!(Difficulty::Easy.next(), Difficulty::Normal);
+        assert_eq!(Difficulty::Normal.next(), Difficulty::Hard);
+        assert_eq!(Difficulty::Hard.next(), Difficulty::Easy);
+        let custom = Difficulty::Custom(Difficulty::Hard.tuning());
+        assert_eq!(custom.next(), Difficulty::Easy);
+    }
+
+    #[test]
+    fn difficulty_survives_serialization() {
+        for difficulty in [
+            Difficulty::Easy,
+            Difficulty::Hard,
+            Difficulty::Custom(NpcTuning {
+                speed_mult: 1.7,
+                ..Difficulty::Normal.tuning()
+            }),
+        ] {
+            let text = ron::to_string(&difficulty).unwrap();
+            assert_eq!(ron::from_str::<Difficulty>(&text).unwrap(), difficulty);
+        }
+    }
+
+    #[test]
+    fn difficulty_change_rescales_live_hostiles() {
+        let mut app = App::new();
+        app.init_resource::<Difficulty>()
+            .add_system(apply_difficulty_change);
+        let (health, damage) = npc_stats(Allegiance::Hostile, &Difficulty::Normal.tuning());
+        let hostile = app
+            .world
+            .spawn()
+            .insert(Allegiance::Hostile)
+            .insert(health)
+            .insert(damage.unwrap())
+            .id();
+        let (health, _) = npc_stats(Allegiance::Friendly, &Difficulty::Normal.tuning());
+        let friendly = app
+            .world
+            .spawn()
+            .insert(Allegiance::Friendly)
+            .insert(health)
+            .id();
+        app.update();
+
+        *app.world.get_resource_mut::<Difficulty>().unwrap() = Difficulty::Hard;
+        app.update();
+        let health = app.world.get::<Health>(hostile).unwrap();
+        assert_eq!((health.max, health.current), (8, 8));
+        assert_eq!(app.world.get::<CollisionDamage>(hostile).unwrap().damage, 2);
+        assert_eq!(
+            app.world.get::<Health>(friendly).unwrap().max,
+            BASE_NPC_HEALTH
+        );
+
+        // Switching back restores the base stats, wounds included
+        app.world.get_mut::<Health>(hostile).unwrap().current = 4;
+        *app.world.get_resource_mut::<Difficulty>().unwrap() = Difficulty::Normal;
+        app.update();
+        let health = app.world.get::<Health>(hostile).unwrap();
+        assert_eq!((health.max, health.current), (5, 3));
+        assert_eq!(app.world.get::<CollisionDamage>(hostile).unwrap().damage, 1);
+    }
+
+    #[test]
+    fn zero_multipliers_leave_hostiles_at_one() {
+        let mut app = App::new();
+        app.init_resource::<Difficulty>()
+            .add_system(apply_difficulty_change);
+        let (health, damage) = npc_stats(Allegiance::Hostile, &Difficulty::Normal.tuning());
+        let hostile = app
+            .world
+            .spawn()
+            .insert(Allegiance::Hostile)
+            .insert(health)
+            .insert(damage.unwrap())
+            .id();
+        app.update();
+
+        for _ in 0..2 {
+            *app.world.get_resource_mut::<Difficulty>().unwrap() = Difficulty::Custom(NpcTuning {
+                health_mult: 0.0,
+                damage_mult: 0.0,
+                ..Difficulty::Normal.tuning()
+            });
+            app.update();
+            let health = app.world.get::<Health>(hostile).unwrap();
+            assert_eq!((health.max, health.current), (1, 1));
+            assert_eq!(app.world.get::<CollisionDamage>(hostile).unwrap().damage, 1);
+
+            // And back up again from there
+            *app.world.get_resource_mut::<Difficulty>().unwrap() = Difficulty::Hard;
+            app.update();
+            let health = app.world.get::<Health>(hostile).unwrap();
+            assert_eq!((health.max, health.current), (8, 8));
+        }
+    }
+
+    #[test]
+    fn broken_npc_tuning_is_rejected() {
+        for preset in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard] {
+            assert!(preset.tuning().validate().is_ok());
+        }
+        for tuning in [
+            NpcTuning {
+                replan_mult: -1.0,
+                ..Difficulty::Normal.tuning()
+            },
+            NpcTuning {
+                replan_mult: 0.0,
+                ..Difficulty::Normal.tuning()
+            },
+            NpcTuning {
+                speed_mult: f32::NAN,
+                ..Difficulty::Normal.tuning()
+            },
+            NpcTuning {
+                sight_range: f32::INFINITY,
+                ..Difficulty::Normal.tuning()
+            },
+        ] {
+            assert!(tuning.validate().is_err(), "{:?}", tuning);
+        }
+    }
+
+    #[test]
+    fn head_on_ball_hits() {
+        // Ball 5 m to the right, flying straight at the NPC
+        let (time, distance) = closest_approach(Vec2::new(5.0, 0.0), Vec2::new(-10.0, 0.0));
+        assert!((time - 0.5).abs() < 1e-6);
+        assert!(distance < 1e-6);
+    }
+
+    #[test]
+    fn passing_ball_misses_by_its_offset() {
+        // Ball 5 m to the right and 2 m up, flying left
+        let (time, distance) = closest_approach(Vec2::new(5.0, 2.0), Vec2::new(-10.0, 0.0));
+        assert!((time - 0.5).abs() < 1e-6);
+        assert!((distance - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn receding_ball_is_closest_now() {
+        let (time, distance) = closest_approach(Vec2::new(5.0, 0.0), Vec2::new(10.0, 0.0));
+        assert_eq!(time, 0.0);
+        assert!((distance - 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parallel_ball_keeps_its_distance() {
+        // Moving alongside, or not moving at all relative to the NPC
+        let (time, distance) = closest_approach(Vec2::new(0.0, 3.0), Vec2::ZERO);
+        assert_eq!(time, 0.0);
+        assert!((distance - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dodging_depends_on_difficulty() {
+        assert!(!Difficulty::Easy.tuning().dodge);
+        assert!(Difficulty::Normal.tuning().dodge);
+        assert!(Difficulty::Hard.tuning().dodge);
+    }
+
+    /// App running only zombie_follow, replanning on every frame
+    fn replan_app() -> App {
+        let mut app = App::new();
+        app.insert_resource(Time::default())
+            .init_resource::<DifficultyCurve>()
+            .init_resource::<Difficulty>()
+            .insert_resource(Tuning {
+                ai: AiTuning {
+                    replan_interval: 0.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert_resource(ReplanTimer(Timer::from_seconds(0.0, true)))
+            .add_system(zombie_follow);
+        app
+    }
+
+    fn spawn_character(app: &mut App, allegiance: Allegiance, x: f32) -> Entity {
+        let position: RigidBodyPositionComponent = Isometry::translation(x, 0.0).into();
+        app.world
+            .spawn()
+            .insert(SimpleFigureTag)
+            .insert(position)
+            .insert(allegiance)
+            .id()
+    }
+
+    #[test]
+    fn dormant_hostiles_do_not_replan() {
+        let mut app = replan_app();
+        spawn_character(&mut app, Allegiance::Friendly, 0.0);
+        let awake = spawn_character(&mut app, Allegiance::Hostile, 5.0);
+        let dormant = spawn_character(&mut app, Allegiance::Hostile, 10.0);
+        app.world.entity_mut(dormant).insert(Dormant);
+
+        for _ in 0..10 {
+            app.update();
+        }
+
+        assert!(app.world.get::<GoalPosition>(awake).is_some());
+        assert!(app.world.get::<GoalPosition>(dormant).is_none());
+    }
+
+    #[test]
+    fn nearest_picks_the_closest_candidate() {
+        let candidates = vec![
+            ("far", Vec2::new(5.0, 0.0)),
+            ("near", Vec2::new(0.0, -1.0)),
+            ("middle", Vec2::new(2.0, 2.0)),
+        ];
+        let (name, _) = nearest(Vec2::ZERO, candidates.into_iter()).unwrap();
+        assert_eq!(name, "near");
+        assert!(nearest(Vec2::ZERO, Vec::<((), Vec2)>::new().into_iter()).is_none());
+    }
+
+    #[test]
+    fn nearest_survives_nan_positions() {
+        let candidates = vec![
+            ("nan", Vec2::new(f32::NAN, 0.0)),
+            ("real", Vec2::new(3.0, 0.0)),
+        ];
+        let (name, _) = nearest(Vec2::ZERO, candidates.into_iter()).unwrap();
+        assert_eq!(name, "real");
+    }
+
+    #[test]
+    fn friendlies_trail_the_player() {
+        let player = Vec2::new(1.0, 1.0);
+        // Close enough already
+        assert_eq!(follow_goal(Vec2::new(2.0, 1.0), player, 1.5), None);
+        // Otherwise stop follow_distance short, on the friendly's side
+        let goal = follow_goal(Vec2::new(5.0, 1.0), player, 1.5).unwrap();
+        assert!((goal - Vec2::new(2.5, 1.0)).length() < 1e-6);
+    }
+}
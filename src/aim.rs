@@ -0,0 +1,697 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::ai::Allegiance;
+use crate::ball::SHOT_SPAWN_OFFSET;
+use crate::ecs::DespawnEvent;
+use crate::editor::EditorMode;
+use crate::health::Health;
+use crate::input::{cursor_world_position, Facing, PlayerTag, WorldCameraQuery};
+use crate::simple_figure::SimpleFigureTag;
+
+/// Bends shots toward nearby targets, for imprecise aiming devices.
+/// Off by default, since mouse aiming is precise enough.
+pub struct AimAssist {
+    pub enabled: bool,
+    /// Half-angle in radians of the cone around the aim where targets count
+    pub cone: f32,
+    /// How far to bend toward the target, from 0 (not at all) to 1 (snap)
+    pub strength: f32,
+}
+
+impl Default for AimAssist {
+    fn default() -> Self {
+        AimAssist {
+            enabled: false,
+            cone: 10.0_f32.to_radians(),
+            strength: 0.5,
+        }
+    }
+}
+
+impl AimAssist {
+    /// Bend a normalized aim direction toward the candidate target whose
+    /// direction is angularly closest, if it lies within the cone and
+    /// is_visible accepts it. Ties keep the first candidate, so the result
+    /// only depends on the order candidates are given in.
+    pub fn apply(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        targets: impl Iterator<Item = (Entity, Vec2)>,
+        is_visible: impl Fn(Entity, Vec2) -> bool,
+    ) -> Vec2 {
+        if !self.enabled || direction == Vec2::ZERO {
+            return direction;
+        }
+
+        let mut best: Option<(f32, Vec2)> = None;
+        for (entity, position) in targets {
+            let to_target = (position - origin).normalize_or_zero();
+            if to_target == Vec2::ZERO {
+                continue;
+            }
+            let angle = direction.angle_between(to_target).abs();
+            if angle > self.cone {
+                continue;
+            }
+            if let Some((best_angle, _)) = best {
+                if angle >= best_angle {
+                    continue;
+                }
+            }
+            if is_visible(entity, position) {
+                best = Some((angle, to_target));
+            }
+        }
+
+        match best {
+            Some((_, to_target)) => direction.lerp(to_target, self.strength).normalize_or_zero(),
+            None => direction,
+        }
+    }
+}
+
+/// What blocks a line of sight: walls, obstacles and characters, but not
+/// balls or pickup sensors
+pub const SIGHT_GROUPS: InteractionGroups = InteractionGroups::new(0b0100, 0b0100);
+
+/// Whether the first collider in SIGHT_GROUPS hit on the straight line from
+/// origin to target belongs to target, ignoring the colliders of the shooter
+pub fn line_of_sight(
+    query_pipeline: &QueryPipeline,
+    collider_set: &QueryPipelineColliderComponentsSet,
+    shooter: Entity,
+    origin: Vec2,
+    target: Entity,
+    target_position: Vec2,
+) -> bool {
+    let delta = target_position - origin;
+    let ray = Ray::new(
+        Point::new(origin.x, origin.y),
+        Vector::new(delta.x, delta.y),
+    );
+    // Cast slightly past the target's center so it can't be missed
+    match query_pipeline.cast_ray(
+        collider_set,
+        &ray,
+        1.1,
+        true,
+        SIGHT_GROUPS,
+        Some(&|handle| handle.entity() != shooter),
+    ) {
+        Some((handle, _)) => handle.entity() == target,
+        None => false,
+    }
+}
+
+/// Dotted line showing where a shot would go, drawn while the fire button is
+/// held
+pub struct TrajectoryPreview {
+    /// Draw the preview whenever not editing, not just while aiming
+    pub always: bool,
+    pub max_bounces: usize,
+    pub max_length: f32, // m
+    /// Muzzle position and direction the current drawing was traced from
+    aim: Option<(Vec2, Vec2)>,
+    drawn: Option<Entity>,
+}
+
+impl Default for TrajectoryPreview {
+    fn default() -> Self {
+        TrajectoryPreview {
+            always: false,
+            max_bounces: 3,
+            max_length: 15.0,
+            aim: None,
+            drawn: None,
+        }
+    }
+}
+
+/// Aim changes smaller than this don't retrace the preview
+const PREVIEW_EPSILON: f32 = 0.01;
+
+/// How far bounced rays start off the surface, so they don't hit it again
+const BOUNCE_OFFSET: f32 = 0.001; // m
+
+const DASH_LENGTH: f32 = 0.25; // m
+const DASH_GAP: f32 = 0.15; // m
+
+/// Mirror a direction off a surface with the given unit normal
+pub fn reflect(direction: Vec2, normal: Vec2) -> Vec2 {
+    direction - 2.0 * direction.dot(normal) * normal
+}
+
+/// Trace a shot from origin along a normalized direction, bouncing off every
+/// collider it hits, for up to max_bounces bounces or max_length meters.
+/// Returns the corners of the path and the first entity hit, if any.
+pub fn trace_trajectory(
+    query_pipeline: &QueryPipeline,
+    collider_set: &QueryPipelineColliderComponentsSet,
+    shooter: Entity,
+    origin: Vec2,
+    direction: Vec2,
+    max_bounces: usize,
+    max_length: f32,
+) -> (Vec<Vec2>, Option<Entity>) {
+    trace_bounces(
+        origin,
+        direction,
+        max_bounces,
+        max_length,
+        |position, direction, max_toi| {
+            let ray = Ray::new(
+                Point::new(position.x, position.y),
+                Vector::new(direction.x, direction.y),
+            );
+            query_pipeline
+                .cast_ray_and_get_normal(
+                    collider_set,
+                    &ray,
+                    max_toi,
+                    true,
+                    InteractionGroups::all(),
+                    Some(&|handle| handle.entity() != shooter),
+                )
+                .map(|(handle, intersection)| {
+                    let normal = Vec2::new(intersection.normal.x, intersection.normal.y);
+                    (handle.entity(), intersection.toi, normal)
+                })
+        },
+    )
+}
+
+/// The bouncing behind trace_trajectory. cast returns the entity, distance
+/// and surface normal of the first hit from a position along a direction,
+/// within a maximum distance.
+fn trace_bounces(
+    origin: Vec2,
+    direction: Vec2,
+    max_bounces: usize,
+    max_length: f32,
+    cast: impl Fn(Vec2, Vec2, f32) -> Option<(Entity, f32, Vec2)>,
+) -> (Vec<Vec2>, Option<Entity>) {
+    let mut points = vec![origin];
+    let mut first_hit = None;
+    let mut position = origin;
+    let mut direction = direction;
+    let mut remaining = max_length;
+
+    for _ in 0..=max_bounces {
+        match cast(position, direction, remaining) {
+            Some((entity, toi, normal)) => {
+                position += direction * toi;
+                remaining -= toi;
+                points.push(position);
+                first_hit.get_or_insert(entity);
+                direction = reflect(direction, normal);
+                position += normal * BOUNCE_OFFSET;
+            }
+            None => {
+                points.push(position + direction * remaining);
+                break;
+            }
+        }
+    }
+
+    (points, first_hit)
+}
+
+pub fn draw_trajectory_preview(
+    mut commands: Commands,
+    rc: Res<RapierConfiguration>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: WorldCameraQuery,
+    editor: Res<EditorMode>,
+    mut preview: ResMut<TrajectoryPreview>,
+    target_lock: Res<TargetLock>,
+    player_query: Query<(Entity, &GlobalTransform), With<PlayerTag>>,
+    damageable_query: Query<(), With<Health>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    let visible = !editor.enabled && (preview.always || buttons.pressed(MouseButton::Left));
+    let aim = match (visible, cursor_world_position(&windows, &camera_query)) {
+        (true, Some(cursor_world_pos)) => player_query.iter().next().map(|(player, player_tf)| {
+            let player_pos = (player_tf.translation / rc.scale).xy();
+            let aim_pos = target_lock
+                .target_position()
+                .unwrap_or(cursor_world_pos / rc.scale);
+            let direction = (aim_pos - player_pos).normalize_or_zero();
+            (
+                player,
+                player_pos + direction * SHOT_SPAWN_OFFSET,
+                direction,
+            )
+        }),
+        _ => None,
+    };
+
+    let unchanged = match (aim, preview.aim) {
+        (Some((_, muzzle, direction)), Some((drawn_muzzle, drawn_direction))) => {
+            muzzle.distance(drawn_muzzle) < PREVIEW_EPSILON
+                && direction.distance(drawn_direction) < PREVIEW_EPSILON
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    if unchanged {
+        return;
+    }
+
+    if let Some(entity) = preview.drawn.take() {
+        despawn.send(DespawnEvent(entity));
+    }
+    preview.aim = aim.map(|(_, muzzle, direction)| (muzzle, direction));
+
+    let (shooter, muzzle, direction) = match aim {
+        Some(aim) => aim,
+        None => return,
+    };
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let (points, first_hit) = trace_trajectory(
+        &query_pipeline,
+        &collider_set,
+        shooter,
+        muzzle,
+        direction,
+        preview.max_bounces,
+        preview.max_length,
+    );
+
+    let mut builder = GeometryBuilder::new();
+    for segment in points.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let length = start.distance(end);
+        let step = (end - start).normalize_or_zero();
+        let mut dash_start = 0.0;
+        while dash_start < length {
+            let dash_end = (dash_start + DASH_LENGTH).min(length);
+            builder = builder.add(&shapes::Line(
+                (start + step * dash_start) * rc.scale,
+                (start + step * dash_end) * rc.scale,
+            ));
+            dash_start = dash_end + DASH_GAP;
+        }
+    }
+
+    let color = match first_hit {
+        Some(entity) if damageable_query.get(entity).is_ok() => Color::rgba(1.0, 0.3, 0.3, 0.8),
+        _ => Color::rgba(1.0, 1.0, 1.0, 0.6),
+    };
+    let entity = commands
+        .spawn_bundle(builder.build(
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(1.5),
+                color,
+            }),
+            Transform::from_translation(Vec3::new(0.0, 0.0, 10.0)),
+        ))
+        .id();
+    preview.drawn = Some(entity);
+}
+
+/// Locks shots onto a hostile, for controllers where precise aiming is hard.
+/// R locks onto the visible hostile closest to the aim, or releases the lock.
+/// Tab cycles through visible hostiles by angle around the player.
+pub struct TargetLock {
+    pub enabled: bool,
+    /// Half-angle in radians of the cone around the aim to lock onto targets in
+    pub cone: f32,
+    pub range: f32, // m
+    /// How long the target may be out of sight before the lock breaks
+    pub occlusion_grace: f32, // seconds
+    target: Option<Entity>,
+    target_position: Option<Vec2>,
+    occluded_for: f32,
+    marker: Option<Entity>,
+}
+
+impl Default for TargetLock {
+    fn default() -> Self {
+        TargetLock {
+            enabled: true,
+            cone: 30.0_f32.to_radians(),
+            range: 12.0,
+            occlusion_grace: 0.5,
+            target: None,
+            target_position: None,
+            occluded_for: 0.0,
+            marker: None,
+        }
+    }
+}
+
+impl TargetLock {
+    /// Where the locked target is this frame, if there is one
+    pub fn target_position(&self) -> Option<Vec2> {
+        self.target_position
+    }
+
+    fn lock(&mut self, commands: &mut Commands, target: Entity, position: Vec2) {
+        if let Some(marker) = self.marker.take() {
+            commands.entity(marker).despawn_recursive();
+        }
+        let marker = commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shapes::Circle {
+                    radius: 20.0,
+                    center: Vec2::ZERO,
+                },
+                DrawMode::Stroke(StrokeMode {
+                    options: StrokeOptions::default().with_line_width(1.5),
+                    color: Color::rgba(1.0, 0.3, 0.3, 0.6),
+                }),
+                Transform::from_xyz(0.0, 0.0, 1.0),
+            ))
+            .id();
+        commands.entity(target).push_children(&[marker]);
+        info!("Locked onto {:?}", target);
+        self.target = Some(target);
+        self.target_position = Some(position);
+        self.occluded_for = 0.0;
+        self.marker = Some(marker);
+    }
+
+    /// Track how long the target has been out of sight. Returns whether it
+    /// has been hidden for too long to keep the lock.
+    fn lost_sight(&mut self, visible: bool, delta_seconds: f32) -> bool {
+        if visible {
+            self.occluded_for = 0.0;
+        } else {
+            self.occluded_for += delta_seconds;
+        }
+        self.occluded_for > self.occlusion_grace
+    }
+
+    fn release(&mut self, despawn: &mut EventWriter<DespawnEvent>) {
+        if let Some(marker) = self.marker.take() {
+            despawn.send(DespawnEvent(marker));
+        }
+        self.target = None;
+        self.target_position = None;
+    }
+}
+
+const LOCK_KEY: KeyCode = KeyCode::R;
+const CYCLE_KEY: KeyCode = KeyCode::Tab;
+
+pub fn update_target_lock(
+    mut commands: Commands,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    rc: Res<RapierConfiguration>,
+    windows: Res<Windows>,
+    camera_query: WorldCameraQuery,
+    mut lock: ResMut<TargetLock>,
+    player_query: Query<(Entity, &GlobalTransform, Option<&Facing>), With<PlayerTag>>,
+    hostiles: Query<(Entity, &RigidBodyPositionComponent, &Allegiance), With<SimpleFigureTag>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    let player = player_query.iter().next();
+    let (player, player_tf, facing) = match (lock.enabled, player) {
+        (true, Some(player)) => player,
+        _ => {
+            if lock.target.is_some() {
+                lock.release(&mut despawn);
+            }
+            return;
+        }
+    };
+    let player_pos = (player_tf.translation / rc.scale).xy();
+
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    let visible = |target: Entity, target_pos: Vec2| {
+        line_of_sight(
+            &query_pipeline,
+            &collider_set,
+            player,
+            player_pos,
+            target,
+            target_pos,
+        )
+    };
+    let candidates: Vec<(Entity, Vec2)> = hostiles
+        .iter()
+        .filter(|(_, _, allegiance)| **allegiance == Allegiance::Hostile)
+        .map(|(entity, position, _)| (entity, position.position.translation.into()))
+        .filter(|(_, position): &(Entity, Vec2)| position.distance(player_pos) <= lock.range)
+        .collect();
+
+    // Keep following the target, breaking the lock once it dies, leaves range
+    // or stays hidden for too long
+    if let Some(target) = lock.target {
+        match candidates.iter().find(|(entity, _)| *entity == target) {
+            Some(&(_, position)) => {
+                lock.target_position = Some(position);
+                if lock.lost_sight(visible(target, position), time.delta_seconds()) {
+                    info!("Lost sight of {:?}", target);
+                    lock.release(&mut despawn);
+                }
+            }
+            None => lock.release(&mut despawn),
+        }
+    }
+
+    if keyboard_input.just_pressed(LOCK_KEY) {
+        if lock.target.is_some() {
+            lock.release(&mut despawn);
+        } else {
+            let aim = match cursor_world_position(&windows, &camera_query) {
+                Some(cursor_world_pos) => {
+                    (cursor_world_pos / rc.scale - player_pos).normalize_or_zero()
+                }
+                None => facing.map_or(Vec2::ZERO, |Facing(facing)| *facing),
+            };
+            if let Some((entity, position)) =
+                select_target(player_pos, aim, lock.cone, &candidates, &visible)
+            {
+                lock.lock(&mut commands, entity, position);
+            }
+        }
+    }
+
+    if keyboard_input.just_pressed(CYCLE_KEY) {
+        if let Some(target) = lock.target {
+            if let Some((next, position)) = next_target(player_pos, target, &candidates, &visible) {
+                lock.lock(&mut commands, next, position);
+            }
+        }
+    }
+}
+
+/// The visible candidate within cone of the aim direction that is closest to
+/// it in angle
+fn select_target(
+    player_pos: Vec2,
+    aim: Vec2,
+    cone: f32,
+    candidates: &[(Entity, Vec2)],
+    visible: impl Fn(Entity, Vec2) -> bool,
+) -> Option<(Entity, Vec2)> {
+    candidates
+        .iter()
+        .map(|&(entity, position)| {
+            let angle = aim.angle_between((position - player_pos).normalize_or_zero());
+            (entity, position, angle.abs())
+        })
+        .filter(|(_, _, angle)| *angle <= cone)
+        .filter(|(entity, position, _)| visible(*entity, *position))
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+        .map(|(entity, position, _)| (entity, position))
+}
+
+/// The visible candidate after target, going counterclockwise around the
+/// player, if there is another one
+fn next_target(
+    player_pos: Vec2,
+    target: Entity,
+    candidates: &[(Entity, Vec2)],
+    visible: impl Fn(Entity, Vec2) -> bool,
+) -> Option<(Entity, Vec2)> {
+    let mut ordered: Vec<(Entity, Vec2, f32)> = candidates
+        .iter()
+        .filter(|(entity, position)| *entity == target || visible(*entity, *position))
+        .map(|&(entity, position)| {
+            let offset = position - player_pos;
+            (entity, position, offset.y.atan2(offset.x))
+        })
+        .collect();
+    ordered.sort_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    let index = ordered
+        .iter()
+        .position(|(entity, _, _)| *entity == target)?;
+    let (next, position, _) = ordered[(index + 1) % ordered.len()];
+    if next == target {
+        None
+    } else {
+        Some((next, position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pickup::PICKUP_GROUPS;
+
+    #[test]
+    fn reflect_mirrors_across_the_normal() {
+        assert_eq!(reflect(Vec2::new(1.0, -1.0), Vec2::Y), Vec2::new(1.0, 1.0));
+        assert_eq!(reflect(Vec2::new(1.0, 1.0), -Vec2::X), Vec2::new(-1.0, 1.0));
+        // Head on comes straight back
+        assert_eq!(reflect(Vec2::X, -Vec2::X), -Vec2::X);
+    }
+
+    /// Cast against the walls x = 1 and y = 3 from the inside of the corner
+    /// they make
+    fn corner_cast(
+        right_wall: Entity,
+        top_wall: Entity,
+    ) -> impl Fn(Vec2, Vec2, f32) -> Option<(Entity, f32, Vec2)> {
+        move |position, direction, max_toi| {
+            let hits = [
+                (right_wall, (1.0 - position.x) / direction.x, -Vec2::X),
+                (top_wall, (3.0 - position.y) / direction.y, -Vec2::Y),
+            ];
+            hits.into_iter()
+                .filter(|(_, toi, _)| *toi > 0.0 && *toi <= max_toi)
+                .min_by(|(_, a, _), (_, b, _)| a.total_cmp(b))
+        }
+    }
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!((a - b).length() < 0.01, "{:?} is not near {:?}", a, b);
+    }
+
+    #[test]
+    fn two_bounces_off_a_corner() {
+        let mut world = World::new();
+        let (right_wall, top_wall) = (world.spawn().id(), world.spawn().id());
+        let direction = Vec2::new(1.0, 1.0).normalize();
+
+        // Up and right from the origin: off the right wall at (1, 1), then
+        // up and left off the top wall at (-1, 3), then down and left
+        let (points, first_hit) = trace_bounces(
+            Vec2::ZERO,
+            direction,
+            2,
+            100.0,
+            corner_cast(right_wall, top_wall),
+        );
+        assert_eq!(first_hit, Some(right_wall));
+        assert_eq!(points.len(), 4);
+        assert_near(points[1], Vec2::new(1.0, 1.0));
+        assert_near(points[2], Vec2::new(-1.0, 3.0));
+        let last_leg = (points[3] - points[2]).normalize();
+        assert_near(last_leg, Vec2::new(-1.0, -1.0).normalize());
+        // The whole path is as long as allowed
+        let length: f32 = points.windows(2).map(|leg| leg[0].distance(leg[1])).sum();
+        assert!((length - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn bounces_stop_at_max_length() {
+        let mut world = World::new();
+        let (right_wall, top_wall) = (world.spawn().id(), world.spawn().id());
+        let direction = Vec2::new(1.0, 1.0).normalize();
+
+        // Only long enough to reach the first wall and come halfway back
+        let (points, _) = trace_bounces(
+            Vec2::ZERO,
+            direction,
+            2,
+            1.5 * 2f32.sqrt(),
+            corner_cast(right_wall, top_wall),
+        );
+        assert_eq!(points.len(), 3);
+        assert_near(points[2], Vec2::new(0.5, 1.5));
+    }
+
+    /// Hostiles right, up and left of a player at the origin, plus one far
+    /// off to the right at a slight angle
+    fn surrounding_hostiles() -> (World, Vec<(Entity, Vec2)>) {
+        let mut world = World::new();
+        let candidates = [
+            Vec2::new(5.0, 0.0),
+            Vec2::new(0.0, 5.0),
+            Vec2::new(-5.0, 0.0),
+            Vec2::new(10.0, 1.0),
+        ]
+        .into_iter()
+        .map(|position| (world.spawn().id(), position))
+        .collect();
+        (world, candidates)
+    }
+
+    #[test]
+    fn lock_picks_the_target_closest_to_the_aim() {
+        let (_world, candidates) = surrounding_hostiles();
+        let cone = 30.0_f32.to_radians();
+
+        // Straight right: the nearer hostile is dead ahead
+        let target = select_target(Vec2::ZERO, Vec2::X, cone, &candidates, |_, _| true);
+        assert_eq!(target, Some(candidates[0]));
+
+        // Aiming a little up: the far one is closer in angle
+        let aim = Vec2::new(10.0, 1.2).normalize();
+        let target = select_target(Vec2::ZERO, aim, cone, &candidates, |_, _| true);
+        assert_eq!(target, Some(candidates[3]));
+
+        // Hidden hostiles are skipped
+        let hidden = candidates[0].0;
+        let target = select_target(Vec2::ZERO, Vec2::X, cone, &candidates, |entity, _| {
+            entity != hidden
+        });
+        assert_eq!(target, Some(candidates[3]));
+
+        // Nothing inside the cone
+        let target = select_target(Vec2::ZERO, -Vec2::Y, cone, &candidates, |_, _| true);
+        assert_eq!(target, None);
+    }
+
+    #[test]
+    fn cycling_goes_counterclockwise_and_wraps() {
+        let (_world, candidates) = surrounding_hostiles();
+        let [right, up, left, far] = [0, 1, 2, 3].map(|i| candidates[i].0);
+        let next = |target| {
+            next_target(Vec2::ZERO, target, &candidates, |_, _| true).map(|(entity, _)| entity)
+        };
+        assert_eq!(next(right), Some(far));
+        assert_eq!(next(far), Some(up));
+        assert_eq!(next(up), Some(left));
+        // From just below pi back around to the smallest angle
+        assert_eq!(next(left), Some(right));
+
+        // Hidden hostiles are skipped, and with none left the lock stays
+        let next_visible = next_target(Vec2::ZERO, right, &candidates, |entity, _| entity == right);
+        assert_eq!(next_visible, None);
+    }
+
+    #[test]
+    fn lock_breaks_after_the_occlusion_grace() {
+        let mut lock = TargetLock::default();
+        assert!(!lock.lost_sight(false, 0.3));
+        // Seeing the target again resets the grace period
+        assert!(!lock.lost_sight(true, 0.1));
+        assert!(!lock.lost_sight(false, 0.3));
+        assert!(!lock.lost_sight(false, 0.1));
+        assert!(lock.lost_sight(false, 0.2));
+    }
+
+    #[test]
+    fn sight_is_blocked_by_walls_and_characters_only() {
+        // Walls and obstacles use the default groups
+        assert!(SIGHT_GROUPS.test(ColliderFlags::default().collision_groups));
+        assert!(SIGHT_GROUPS.test(InteractionGroups::new(0b0111, 0b0111)));
+        // Balls and pickups
+        assert!(!SIGHT_GROUPS.test(InteractionGroups::new(0b0011, 0b0011)));
+        assert!(!SIGHT_GROUPS.test(PICKUP_GROUPS));
+    }
+}
@@ -0,0 +1,137 @@
+use bevy::asset::LoadState;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+/// Tracks the assets gameplay depends on and reports any that fail to load,
+/// replacing them with placeholders instead of letting entities spawn with
+/// missing textures.
+pub struct AssetPreloadPlugin;
+
+impl Plugin for AssetPreloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PreloadAssets>()
+            .init_resource::<AssetsReady>()
+            .init_resource::<LoadingProgress>()
+            .add_system(check_assets);
+    }
+}
+
+/// Textures that must finish loading before gameplay spawns. Registering more
+/// later, e.g. a map's tileset, holds gameplay spawning until they load too.
+#[derive(Default)]
+pub struct PreloadAssets(pub Vec<HandleUntyped>);
+
+impl PreloadAssets {
+    /// Register a handle from a FromWorld resource, regardless of plugin order
+    pub fn register(world: &mut World, handle: HandleUntyped) {
+        world
+            .get_resource_or_insert_with(PreloadAssets::default)
+            .0
+            .push(handle);
+    }
+}
+
+#[derive(Default)]
+pub struct AssetsReady(pub bool);
+
+/// How many of the preloaded assets have finished loading, successfully or not
+#[derive(Default, Debug, PartialEq)]
+pub struct LoadingProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Run criteria for systems that spawn entities using preloaded assets
+pub fn assets_ready(ready: Res<AssetsReady>) -> ShouldRun {
+    if ready.0 {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Bright magenta, so placeholders stand out
+const PLACEHOLDER_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// Solid colored square to stand in for a texture that failed to load
+fn placeholder_image() -> Image {
+    Image::new_fill(
+        Extent3d {
+            width: 32,
+            height: 32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &PLACEHOLDER_COLOR,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn check_assets(
+    asset_server: Res<AssetServer>,
+    preload: Res<PreloadAssets>,
+    mut images: ResMut<Assets<Image>>,
+    mut ready: ResMut<AssetsReady>,
+    mut progress: ResMut<LoadingProgress>,
+    mut windows: ResMut<Windows>,
+) {
+    if preload.is_changed() {
+        ready.0 = false;
+    }
+    if ready.0 {
+        return;
+    }
+
+    let current = LoadingProgress {
+        done: preload
+            .0
+            .iter()
+            .filter(|handle| {
+                matches!(
+                    asset_server.get_load_state(handle.id),
+                    LoadState::Loaded | LoadState::Failed
+                )
+            })
+            .count(),
+        total: preload.0.len(),
+    };
+    if current != *progress {
+        info!("Loading assets: {}/{}", current.done, current.total);
+        *progress = current;
+    }
+
+    match asset_server.get_group_load_state(preload.0.iter().map(|handle| handle.id)) {
+        LoadState::Loaded => {
+            info!("All {} preloaded assets are ready", preload.0.len());
+            ready.0 = true;
+        }
+        LoadState::Failed => {
+            // Wait for the rest to finish before deciding what is missing
+            if progress.done < progress.total {
+                return;
+            }
+
+            let mut missing = Vec::new();
+            for handle in preload.0.iter() {
+                if asset_server.get_load_state(handle.id) != LoadState::Failed {
+                    continue;
+                }
+                let path = match asset_server.get_handle_path(handle.id) {
+                    Some(path) => path.path().display().to_string(),
+                    None => format!("{:?}", handle.id),
+                };
+                warn!("Failed to load asset, using a placeholder: {}", path);
+                images.set_untracked(handle.id, placeholder_image());
+                missing.push(path);
+            }
+            // There is no font to render a warning with, so surface it in the
+            // window title where it can't be missed
+            if let Some(window) = windows.get_primary_mut() {
+                window.set_title(format!("Missing assets: {}", missing.join(", ")));
+            }
+            ready.0 = true;
+        }
+        _ => (),
+    }
+}
@@ -1,38 +1,128 @@
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use bevy_rapier2d::prelude::*;
 use nalgebra::Isometry2;
+use std::collections::VecDeque;
 
-use crate::health::CollisionDamage;
+use crate::assets::PreloadAssets;
+use crate::ecs::DespawnEvent;
 use crate::health::Health;
+use crate::health::{CollisionDamage, CollisionSelfDamage};
+use crate::tuning::Tuning;
 
 pub struct BallPlugin;
 
-/// Resource for holding texture atlas
-pub struct BallTextureHandle(Handle<Image>);
+/// Kinds of projectile that can be fired, each with its own look
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProjectileKind {
+    Baseball,
+    Pellet,
+    Boulder,
+}
+
+impl Default for ProjectileKind {
+    fn default() -> Self {
+        ProjectileKind::Baseball
+    }
+}
+
+impl ProjectileKind {
+    /// The next kind, for cycling through weapons
+    pub fn next(self) -> Self {
+        match self {
+            ProjectileKind::Baseball => ProjectileKind::Pellet,
+            ProjectileKind::Pellet => ProjectileKind::Boulder,
+            ProjectileKind::Boulder => ProjectileKind::Baseball,
+        }
+    }
+}
+
+pub struct ProjectileAppearance {
+    pub texture: Handle<Image>,
+    pub color: Color,
+    /// Collider radius in physics meters. The sprite is scaled to match.
+    pub radius: f32,
+}
+
+const BASEBALL_RADIUS: f32 = 0.1; // m
+
+/// Resource holding the appearance of each projectile kind
+pub struct ProjectileAppearances(HashMap<ProjectileKind, ProjectileAppearance>);
 
-impl FromWorld for BallTextureHandle {
+impl ProjectileAppearances {
+    pub fn get(&self, kind: ProjectileKind) -> &ProjectileAppearance {
+        &self.0[&kind]
+    }
+}
+
+impl FromWorld for ProjectileAppearances {
     fn from_world(world: &mut World) -> Self {
         let asset_server = world.get_resource::<AssetServer>().unwrap();
-        let image = asset_server.load("spritesheets/baseball.png");
-        BallTextureHandle(image)
+        let image: Handle<Image> = asset_server.load("spritesheets/baseball.png");
+        PreloadAssets::register(world, image.clone_untyped());
+
+        let mut appearances = HashMap::default();
+        appearances.insert(
+            ProjectileKind::Baseball,
+            ProjectileAppearance {
+                texture: image.clone(),
+                color: Color::WHITE,
+                radius: BASEBALL_RADIUS,
+            },
+        );
+        appearances.insert(
+            ProjectileKind::Pellet,
+            ProjectileAppearance {
+                texture: image.clone(),
+                color: Color::YELLOW,
+                radius: 0.05,
+            },
+        );
+        appearances.insert(
+            ProjectileKind::Boulder,
+            ProjectileAppearance {
+                texture: image,
+                color: Color::GRAY,
+                radius: 0.2,
+            },
+        );
+        ProjectileAppearances(appearances)
     }
 }
 
 impl Plugin for BallPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<BallSpawnEvent>()
-            .init_resource::<BallTextureHandle>()
-            .add_system(spawn);
+            .init_resource::<ProjectileAppearances>()
+            .init_resource::<MaxBalls>()
+            .init_resource::<BallSpawnOrder>()
+            .init_resource::<RejectedBallSpawns>()
+            .add_system(spawn)
+            .add_system(limit_balls);
     }
 }
 
 #[derive(Component)]
 pub struct BallTag;
 
+/// Most balls alive at once. Beyond this the oldest are despawned.
+pub struct MaxBalls(pub usize);
+
+impl Default for MaxBalls {
+    fn default() -> Self {
+        MaxBalls(100)
+    }
+}
+
+/// Live balls, oldest first
+#[derive(Default)]
+struct BallSpawnOrder(VecDeque<Entity>);
+
 #[derive(Bundle)]
 pub struct BallBundle {
     tag: BallTag,
     collision_damage: CollisionDamage,
+    collision_self_damage: CollisionSelfDamage,
     #[bundle]
     rigid_body_bundle: RigidBodyBundle,
     position_sync: RigidBodyPositionSync,
@@ -49,9 +139,11 @@ impl Default for BallBundle {
             tag: BallTag,
             rigid_body_bundle: Default::default(),
             collision_damage: CollisionDamage { damage: 1 },
+            // Balls are used up by the first character they hit
+            collision_self_damage: CollisionSelfDamage { damage: 1 },
             position_sync: RigidBodyPositionSync::Discrete,
             collider_bundle: ColliderBundle {
-                shape: ColliderShape::ball(0.1).into(),
+                shape: ColliderShape::ball(BASEBALL_RADIUS).into(),
                 flags: ColliderFlags {
                     collision_groups: InteractionGroups::new(0b0011, 0b0011),
                     active_events: ActiveEvents::CONTACT_EVENTS,
@@ -73,50 +165,365 @@ impl Default for BallBundle {
     }
 }
 
+/// How far in front of the shooter shots spawn, clear of its own collider
+pub const SHOT_SPAWN_OFFSET: f32 = 1.0; // m
+
+/// The entity that fired a ball
+#[derive(Component)]
+pub struct Owner(pub Entity);
+
+/// Request to spawn a ball. Every emitter goes through this, so spawn
+/// parameters are validated in one place.
+#[derive(Debug)]
 pub struct BallSpawnEvent {
-    pub position: Isometry2<f32>,
+    /// Physics meters
+    pub position: Vec2,
+    /// m/s
     pub velocity: Vec2,
+    pub kind: ProjectileKind,
+    pub owner: Option<Entity>,
 }
 
 impl Default for BallSpawnEvent {
     fn default() -> Self {
         BallSpawnEvent {
-            position: Isometry2::identity(),
+            position: Vec2::ZERO,
             velocity: Vec2::ZERO,
+            kind: ProjectileKind::default(),
+            owner: None,
         }
     }
 }
 
+/// Fastest a ball may be spawned at
+pub const MAX_BALL_SPEED: f32 = 30.0; // m/s
+
+impl BallSpawnEvent {
+    /// A shot fired by owner from origin in a normalized direction, at speed
+    /// in m/s
+    pub fn shot(
+        origin: Vec2,
+        direction: Vec2,
+        speed: f32,
+        kind: ProjectileKind,
+        owner: Entity,
+    ) -> Self {
+        BallSpawnEvent {
+            position: origin + direction * SHOT_SPAWN_OFFSET,
+            velocity: direction * speed,
+            kind,
+            owner: Some(owner),
+        }
+    }
+
+    /// Reject non-finite positions and velocities, and clamp the speed
+    pub fn validate(&self) -> Result<BallSpawnEvent, String> {
+        if !self.position.is_finite() {
+            return Err(format!("position {:?} is not finite", self.position));
+        }
+        if !self.velocity.is_finite() {
+            return Err(format!("velocity {:?} is not finite", self.velocity));
+        }
+        Ok(BallSpawnEvent {
+            position: self.position,
+            velocity: self.velocity.clamp_length_max(MAX_BALL_SPEED),
+            kind: self.kind,
+            owner: self.owner,
+        })
+    }
+}
+
+/// Number of ball spawn events rejected as invalid this session
+#[derive(Default)]
+pub struct RejectedBallSpawns(pub usize);
+
 /// Spawn entities in response to spawn events
 fn spawn(
     mut commands: Commands,
     mut spawn_events: EventReader<BallSpawnEvent>,
-    texture_handle: Res<BallTextureHandle>,
+    appearances: Res<ProjectileAppearances>,
+    tuning: Res<Tuning>,
+    mut rejected: ResMut<RejectedBallSpawns>,
 ) {
     for spawn_event in spawn_events.iter() {
-        commands.spawn_bundle(BallBundle {
-            rigid_body_bundle: RigidBodyBundle {
-                mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
-                forces: RigidBodyForces {
-                    gravity_scale: 0.0,
-                    ..Default::default()
-                }
-                .into(),
-                velocity: RigidBodyVelocity {
-                    linvel: spawn_event.velocity.into(),
-                    ..Default::default()
-                }
-                .into(),
-                position: spawn_event.position.into(),
+        // Rejections are already counted and logged
+        let _ = spawn_ball(
+            &mut commands,
+            &appearances,
+            tuning.combat.ball_damage,
+            spawn_event,
+            &mut rejected,
+        );
+    }
+}
+
+/// Spawn a single ball immediately, for callers that need its entity.
+/// Invalid events are counted in rejected and logged, like those sent as
+/// events.
+pub fn spawn_ball(
+    commands: &mut Commands,
+    appearances: &ProjectileAppearances,
+    damage: i32,
+    spawn_event: &BallSpawnEvent,
+    rejected: &mut RejectedBallSpawns,
+) -> Result<Entity, String> {
+    match spawn_event.validate() {
+        Ok(spawn_event) => Ok(spawn_valid_ball(
+            commands,
+            appearances,
+            damage,
+            &spawn_event,
+        )),
+        Err(err) => {
+            rejected.0 += 1;
+            warn!("Rejected ball spawn: {}", err);
+            Err(err)
+        }
+    }
+}
+
+fn spawn_valid_ball(
+    commands: &mut Commands,
+    appearances: &ProjectileAppearances,
+    damage: i32,
+    spawn_event: &BallSpawnEvent,
+) -> Entity {
+    let appearance = appearances.get(spawn_event.kind);
+    let default_bundle = BallBundle::default();
+    let mut entity_commands = commands.spawn_bundle(BallBundle {
+        collision_damage: CollisionDamage { damage },
+        rigid_body_bundle: RigidBodyBundle {
+            mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
+            forces: RigidBodyForces {
+                gravity_scale: 0.0,
                 ..Default::default()
-            },
-            sprite_bundle: SpriteBundle {
-                texture: texture_handle.0.clone(),
-                transform: Transform::from_scale(Vec3::splat(1.0))
-                    * Transform::from_translation(Vec3::new(0.0, 0.0, 2.0)),
+            }
+            .into(),
+            velocity: RigidBodyVelocity {
+                linvel: spawn_event.velocity.into(),
+                ..Default::default()
+            }
+            .into(),
+            position: Isometry2::new(spawn_event.position.into(), 0.0).into(),
+            ..Default::default()
+        },
+        collider_bundle: ColliderBundle {
+            shape: ColliderShape::ball(appearance.radius).into(),
+            ..default_bundle.collider_bundle
+        },
+        sprite_bundle: SpriteBundle {
+            sprite: Sprite {
+                color: appearance.color,
                 ..Default::default()
             },
+            texture: appearance.texture.clone(),
+            transform: Transform::from_scale(Vec3::splat(appearance.radius / BASEBALL_RADIUS))
+                * Transform::from_translation(Vec3::new(0.0, 0.0, 2.0)),
             ..Default::default()
-        });
+        },
+        ..default_bundle
+    });
+    if let Some(owner) = spawn_event.owner {
+        entity_commands.insert(Owner(owner));
+    }
+    entity_commands.id()
+}
+
+fn limit_balls(
+    max_balls: Res<MaxBalls>,
+    mut order: ResMut<BallSpawnOrder>,
+    new_balls: Query<Entity, Added<BallTag>>,
+    balls: Query<(), With<BallTag>>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    order.0.extend(new_balls.iter());
+    order.0.retain(|entity| balls.get(*entity).is_ok());
+    while order.0.len() > max_balls.0 {
+        if let Some(oldest) = order.0.pop_front() {
+            despawn.send(DespawnEvent(oldest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::DespawnPlugin;
+    use bevy::ecs::system::CommandQueue;
+
+    fn ball_app() -> App {
+        let mut appearances = HashMap::default();
+        for kind in [
+            ProjectileKind::Baseball,
+            ProjectileKind::Pellet,
+            ProjectileKind::Boulder,
+        ] {
+            appearances.insert(
+                kind,
+                ProjectileAppearance {
+                    texture: Handle::default(),
+                    color: Color::WHITE,
+                    radius: BASEBALL_RADIUS,
+                },
+            );
+        }
+
+        let mut app = App::new();
+        app.add_event::<BallSpawnEvent>()
+            .insert_resource(ProjectileAppearances(appearances))
+            .init_resource::<Tuning>()
+            .init_resource::<RejectedBallSpawns>()
+            .add_system(spawn);
+        app
+    }
+
+    fn send(app: &mut App, spawn_event: BallSpawnEvent) {
+        app.world
+            .get_resource_mut::<Events<BallSpawnEvent>>()
+            .unwrap()
+            .send(spawn_event);
+        app.update();
+    }
+
+    fn ball_count(app: &mut App) -> usize {
+        app.world
+            .query_filtered::<(), With<BallTag>>()
+            .iter(&app.world)
+            .count()
+    }
+
+    fn rejected(app: &App) -> usize {
+        app.world.get_resource::<RejectedBallSpawns>().unwrap().0
+    }
+
+    #[test]
+    fn nan_velocity_is_rejected() {
+        let spawn_event = BallSpawnEvent {
+            velocity: Vec2::new(f32::NAN, 1.0),
+            ..Default::default()
+        };
+        assert!(spawn_event.validate().is_err());
+
+        let mut app = ball_app();
+        send(&mut app, spawn_event);
+        assert_eq!(ball_count(&mut app), 0);
+        assert_eq!(rejected(&app), 1);
+    }
+
+    #[test]
+    fn nan_position_is_rejected() {
+        let spawn_event = BallSpawnEvent {
+            position: Vec2::new(1.0, f32::INFINITY),
+            ..Default::default()
+        };
+        assert!(spawn_event.validate().is_err());
+    }
+
+    #[test]
+    fn huge_velocity_is_clamped() {
+        let spawn_event = BallSpawnEvent {
+            velocity: Vec2::new(1e9, 0.0),
+            ..Default::default()
+        };
+        let validated = spawn_event.validate().unwrap();
+        assert_eq!(validated.velocity, Vec2::new(MAX_BALL_SPEED, 0.0));
+
+        let mut app = ball_app();
+        send(&mut app, spawn_event);
+        assert_eq!(ball_count(&mut app), 1);
+        assert_eq!(rejected(&app), 0);
+        let velocity = app
+            .world
+            .query_filtered::<&RigidBodyVelocityComponent, With<BallTag>>()
+            .iter(&app.world)
+            .next()
+            .unwrap()
+            .linvel;
+        assert_eq!(velocity, Vector::new(MAX_BALL_SPEED, 0.0));
+    }
+
+    #[test]
+    fn valid_event_spawns_one_configured_ball() {
+        let mut app = ball_app();
+        let owner = app.world.spawn().id();
+        send(
+            &mut app,
+            BallSpawnEvent::shot(
+                Vec2::new(2.0, 3.0),
+                Vec2::X,
+                10.0,
+                ProjectileKind::Pellet,
+                owner,
+            ),
+        );
+        assert_eq!(ball_count(&mut app), 1);
+        assert_eq!(rejected(&app), 0);
+
+        let (position, velocity, flags, ball_owner) = app
+            .world
+            .query_filtered::<(
+                &RigidBodyPositionComponent,
+                &RigidBodyVelocityComponent,
+                &ColliderFlagsComponent,
+                &Owner,
+            ), With<BallTag>>()
+            .iter(&app.world)
+            .next()
+            .unwrap();
+        let translation: Vec2 = position.position.translation.into();
+        assert_eq!(translation, Vec2::new(2.0 + SHOT_SPAWN_OFFSET, 3.0));
+        assert_eq!(velocity.linvel, Vector::new(10.0, 0.0));
+        assert_eq!(
+            flags.collision_groups,
+            InteractionGroups::new(0b0011, 0b0011)
+        );
+        assert_eq!(ball_owner.0, owner);
+    }
+
+    #[test]
+    fn direct_spawns_are_validated_like_events() {
+        let mut app = ball_app();
+        let mut queue = CommandQueue::default();
+        let mut rejected = RejectedBallSpawns::default();
+        let appearances = app.world.get_resource::<ProjectileAppearances>().unwrap();
+        let mut commands = Commands::new(&mut queue, &app.world);
+        let invalid = BallSpawnEvent {
+            position: Vec2::new(f32::NAN, 0.0),
+            ..Default::default()
+        };
+        assert!(spawn_ball(&mut commands, appearances, 1, &invalid, &mut rejected).is_err());
+        let valid = BallSpawnEvent::default();
+        let ball = spawn_ball(&mut commands, appearances, 1, &valid, &mut rejected).unwrap();
+        queue.apply(&mut app.world);
+
+        assert_eq!(rejected.0, 1);
+        assert_eq!(ball_count(&mut app), 1);
+        assert!(app.world.get::<BallTag>(ball).is_some());
+    }
+
+    #[test]
+    fn ball_count_stays_at_the_cap() {
+        let mut app = App::new();
+        app.add_plugin(DespawnPlugin)
+            .insert_resource(MaxBalls(3))
+            .init_resource::<BallSpawnOrder>()
+            .add_system(limit_balls);
+
+        let balls: Vec<Entity> = (0..5)
+            .map(|_| app.world.spawn().insert(BallTag).id())
+            .collect();
+        app.update();
+        assert_eq!(ball_count(&mut app), 3);
+        // The oldest went first
+        assert!(app.world.get_entity(balls[0]).is_none());
+        assert!(app.world.get_entity(balls[1]).is_none());
+
+        for _ in 0..2 {
+            app.world.spawn().insert(BallTag);
+        }
+        app.update();
+        assert_eq!(ball_count(&mut app), 3);
+        assert!(app.world.get_entity(balls[2]).is_none());
+        assert!(app.world.get_entity(balls[4]).is_some());
     }
 }
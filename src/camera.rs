@@ -1,11 +1,23 @@
 use bevy::prelude::*;
-use bevy::render::camera::Camera;
+use bevy::render::camera::{Camera, OrthographicProjection};
+use bevy::transform::TransformSystem;
+use bevy_rapier2d::prelude::*;
+
+use crate::tuning::Tuning;
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup).add_system(camera_follow);
+        // Follow the target after physics has synced its Transform, but before
+        // transforms are propagated, so the camera isn't a frame behind
+        app.init_resource::<CameraLead>()
+            .init_resource::<CameraFraming>()
+            .add_startup_system(setup)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                camera_follow.before(TransformSystem::TransformPropagate),
+            );
     }
 }
 
@@ -16,30 +28,123 @@ fn setup(mut commands: Commands) {
 #[derive(Component)]
 pub struct CameraTarget;
 
-const X_DEAD_ZONE: f32 = 32.0;
-const Y_DEAD_ZONE: f32 = 32.0;
+/// Shifts the camera ahead of a moving target, so more of where it is going
+/// is visible
+pub struct CameraLead {
+    /// Seconds of target velocity to lead by. 0 disables leading.
+    pub factor: f32,
+    /// Longest lead, in pixels
+    pub max: f32,
+    /// How quickly the lead catches up with changes in velocity, per second
+    pub smoothing: f32,
+    current: Vec2,
+}
+
+impl Default for CameraLead {
+    fn default() -> Self {
+        CameraLead {
+            factor: 0.25,
+            max: 96.0,
+            smoothing: 4.0,
+            current: Vec2::ZERO,
+        }
+    }
+}
+
+/// How the camera keeps several targets in view, for shared-screen play.
+/// The camera follows the centroid of all targets, and zooms out to fit them.
+pub struct CameraFraming {
+    /// Most targets framed at once. Any beyond this are ignored.
+    pub max_targets: usize,
+    pub zoom_to_fit: bool,
+    /// Space to keep between the outermost targets and the screen edge
+    pub padding: f32, // px
+    /// Furthest the camera may zoom out, as a projection scale
+    pub max_zoom: f32,
+    /// How quickly the zoom catches up with the targets, per second
+    pub smoothing: f32,
+}
+
+impl Default for CameraFraming {
+    fn default() -> Self {
+        CameraFraming {
+            max_targets: 4,
+            zoom_to_fit: true,
+            padding: 96.0,
+            max_zoom: 2.0,
+            smoothing: 2.0,
+        }
+    }
+}
 
 fn camera_follow(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    rapier_config: Res<RapierConfiguration>,
+    tuning: Res<Tuning>,
+    framing: Res<CameraFraming>,
+    mut lead: ResMut<CameraLead>,
     mut q: QuerySet<(
-        QueryState<(&CameraTarget, &Transform)>,
-        QueryState<(&Camera, &mut Transform)>,
+        QueryState<(
+            &CameraTarget,
+            &Transform,
+            Option<&RigidBodyVelocityComponent>,
+        )>,
+        QueryState<(&Camera, &mut Transform, &mut OrthographicProjection)>,
     )>,
 ) {
-    let translation = if let Some((_tag, target_transform)) = q.q0().iter().next() {
-        Some(target_transform.translation)
+    let mut count = 0;
+    let mut sum = Vec2::ZERO;
+    let mut velocity_sum = Vec2::ZERO;
+    let mut min = Vec2::splat(f32::INFINITY);
+    let mut max = Vec2::splat(f32::NEG_INFINITY);
+    for (_tag, target_transform, velocity) in q.q0().iter().take(framing.max_targets) {
+        let position = target_transform.translation.truncate();
+        count += 1;
+        sum += position;
+        velocity_sum += velocity.map_or(Vec2::ZERO, |velocity| velocity.linvel.into());
+        min = min.min(position);
+        max = max.max(position);
+    }
+
+    let translation = if count > 0 {
+        let velocity = velocity_sum / count as f32;
+        let target_lead = (velocity * rapier_config.scale * lead.factor).clamp_length_max(lead.max);
+        let t = (lead.smoothing * time.delta_seconds()).min(1.0);
+        lead.current = lead.current.lerp(target_lead, t);
+        Some(sum / count as f32 + lead.current)
     } else {
         None
     };
 
-    if let Some((_current_camera, mut camera_transform)) = q.q1().iter_mut().next() {
+    // Zoom out just enough to fit every target, but never in past 1:1
+    let zoom = match windows.get_primary() {
+        Some(window) if framing.zoom_to_fit && count > 1 => {
+            let window_size = Vec2::new(window.width(), window.height());
+            let needed = (max - min + Vec2::splat(2.0 * framing.padding)) / window_size;
+            needed.max_element().clamp(1.0, framing.max_zoom)
+        }
+        _ => 1.0,
+    };
+
+    if let Some((_current_camera, mut camera_transform, mut projection)) = q.q1().iter_mut().next()
+    {
+        let t = (framing.smoothing * time.delta_seconds()).min(1.0);
+        let scale = projection.scale + (zoom - projection.scale) * t;
+        // Only touch the projection when it changes, since that recomputes it
+        if (scale - projection.scale).abs() > f32::EPSILON {
+            projection.scale = scale;
+        }
+
         if let Some(translation) = translation {
+            let dead_zone = tuning.camera.dead_zone;
             let x_diff = translation.x - camera_transform.translation.x;
             let y_diff = translation.y - camera_transform.translation.y;
-            if x_diff.abs() > X_DEAD_ZONE {
-                camera_transform.translation.x = translation.x - x_diff.signum() * X_DEAD_ZONE;
+            if x_diff.abs() > dead_zone {
+                camera_transform.translation.x = translation.x - x_diff.signum() * dead_zone;
             }
-            if y_diff.abs() > Y_DEAD_ZONE {
-                camera_transform.translation.y = translation.y - y_diff.signum() * X_DEAD_ZONE;
+            if y_diff.abs() > dead_zone {
+                camera_transform.translation.y = translation.y - y_diff.signum() * dead_zone;
             }
         }
     }
@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::na::Point2;
+use bevy_rapier2d::prelude::*;
+
+use crate::ecs::DespawnEvent;
+use crate::tiled::TiledMapComponent;
+
+/// Debug overlays, each toggled independently by a key
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ColliderWireframes>()
+            .init_resource::<GridOverlay>()
+            .add_system(toggle_collider_wireframes)
+            .add_system(draw_collider_wireframes)
+            .add_system(toggle_grid_overlay)
+            .add_system(draw_grid_overlay);
+    }
+}
+
+/// Whether to outline every collider, toggled with F3
+#[derive(Default)]
+pub struct ColliderWireframes {
+    pub enabled: bool,
+    drawn: Option<Entity>,
+}
+
+fn toggle_collider_wireframes(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut wireframes: ResMut<ColliderWireframes>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        wireframes.enabled = !wireframes.enabled;
+        info!("Collider wireframes enabled: {}", wireframes.enabled);
+    }
+}
+
+/// Redraw the outline of every collider each frame, since most of them move
+fn draw_collider_wireframes(
+    mut commands: Commands,
+    rc: Res<RapierConfiguration>,
+    mut wireframes: ResMut<ColliderWireframes>,
+    colliders: Query<(&ColliderPositionComponent, &ColliderShapeComponent)>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    if let Some(entity) = wireframes.drawn.take() {
+        despawn.send(DespawnEvent(entity));
+    }
+
+    if !wireframes.enabled {
+        return;
+    }
+
+    let mut builder = GeometryBuilder::new();
+    for (position, shape) in colliders.iter() {
+        match shape.shape_type() {
+            ShapeType::Cuboid => {
+                let half_extents = shape.as_cuboid().unwrap().half_extents;
+                let points = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .iter()
+                    .map(|(x, y)| {
+                        let corner = position.transform_point(&Point2::new(
+                            x * half_extents[0],
+                            y * half_extents[1],
+                        ));
+                        Vec2::new(corner.x, corner.y) * rc.scale
+                    })
+                    .collect();
+                builder = builder.add(&shapes::Polygon {
+                    points,
+                    closed: true,
+                });
+            }
+            ShapeType::Ball => {
+                let center = position.translation;
+                builder = builder.add(&shapes::Circle {
+                    radius: shape.as_ball().unwrap().radius * rc.scale,
+                    center: Vec2::new(center.x, center.y) * rc.scale,
+                });
+            }
+            // The sandbox only spawns cuboid and ball colliders
+            _ => (),
+        }
+    }
+
+    let entity = commands
+        .spawn_bundle(builder.build(
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(1.0),
+                color: Color::FUCHSIA,
+            }),
+            Transform::from_translation(Vec3::new(0.0, 0.0, 20.0)),
+        ))
+        .id();
+    wireframes.drawn = Some(entity);
+}
+
+/// Whether to draw the map's tile grid, toggled with F4
+#[derive(Default)]
+pub struct GridOverlay {
+    pub enabled: bool,
+    drawn: Vec<Entity>,
+}
+
+fn toggle_grid_overlay(keyboard_input: Res<Input<KeyCode>>, mut grid: ResMut<GridOverlay>) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        grid.enabled = !grid.enabled;
+        info!("Grid overlay enabled: {}", grid.enabled);
+    }
+}
+
+/// Every this many tiles the grid line is drawn heavier, as a coordinate guide
+const GRID_MAJOR_INTERVAL: u32 = 10;
+
+fn draw_grid_overlay(
+    mut commands: Commands,
+    mut grid: ResMut<GridOverlay>,
+    maps: Query<&TiledMapComponent>,
+    changed_maps: Query<(), Changed<TiledMapComponent>>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    let redraw = grid.is_changed() || changed_maps.iter().next().is_some();
+    if !redraw {
+        return;
+    }
+
+    for entity in grid.drawn.drain(..) {
+        despawn.send(DespawnEvent(entity));
+    }
+
+    if !grid.enabled {
+        return;
+    }
+
+    for TiledMapComponent(tiled_map) in maps.iter() {
+        // Tiles span from their bottom-left corner at (column, row) * tile size
+        let tile_size = Vec2::new(tiled_map.tile_width as f32, tiled_map.tile_height as f32);
+        let map_size = Vec2::new(tiled_map.width as f32, tiled_map.height as f32) * tile_size;
+
+        let mut minor = GeometryBuilder::new();
+        let mut major = GeometryBuilder::new();
+        for column in 0..=tiled_map.width {
+            let x = column as f32 * tile_size.x;
+            let line = shapes::Line(Vec2::new(x, 0.0), Vec2::new(x, map_size.y));
+            if column % GRID_MAJOR_INTERVAL == 0 {
+                major = major.add(&line);
+            } else {
+                minor = minor.add(&line);
+            }
+        }
+        for row in 0..=tiled_map.height {
+            let y = row as f32 * tile_size.y;
+            let line = shapes::Line(Vec2::new(0.0, y), Vec2::new(map_size.x, y));
+            if row % GRID_MAJOR_INTERVAL == 0 {
+                major = major.add(&line);
+            } else {
+                minor = minor.add(&line);
+            }
+        }
+
+        for (builder, line_width) in [(minor, 0.5), (major, 2.0)] {
+            let entity = commands
+                .spawn_bundle(builder.build(
+                    DrawMode::Stroke(StrokeMode {
+                        options: StrokeOptions::default().with_line_width(line_width),
+                        color: Color::rgba(0.0, 0.0, 0.0, 0.5),
+                    }),
+                    Transform::from_translation(Vec3::new(0.0, 0.0, 15.0)),
+                ))
+                .id();
+            grid.drawn.push(entity);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::ecs::entity::Entities;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use nalgebra::Isometry2;
+
+use crate::ai::Difficulty;
+use crate::assets::assets_ready;
+use crate::ball::{spawn_ball, BallSpawnEvent, ProjectileAppearances, RejectedBallSpawns};
+use crate::ecs::DespawnEvent;
+use crate::input::{cursor_world_position, WorldCameraQuery};
+use crate::obstacle::spawn_obstacle;
+use crate::simple_figure::{
+    spawn_simple_figure, SimpleFigureAnimationHandles, SimpleFigureSpawnEvent,
+    SimpleFigureTextureAtlasHandle,
+};
+use crate::tuning::Tuning;
+
+/// Lightweight in-game editor for placing entities by clicking.
+///
+/// F2 toggles the editor, 1/2/3 select what to place and Ctrl+Z removes the
+/// most recently placed entity.
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorMode>()
+            .init_resource::<EditorHistory>()
+            .add_system(toggle_editor)
+            .add_system(select_item)
+            .add_system(place.with_run_criteria(assets_ready))
+            .add_system(undo);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditorItem {
+    Obstacle,
+    Npc,
+    Ball,
+}
+
+pub struct EditorMode {
+    pub enabled: bool,
+    pub selected: EditorItem,
+}
+
+impl Default for EditorMode {
+    fn default() -> Self {
+        EditorMode {
+            enabled: false,
+            selected: EditorItem::Obstacle,
+        }
+    }
+}
+
+/// Entities placed by the editor, most recent last
+#[derive(Default)]
+pub struct EditorHistory(pub Vec<Entity>);
+
+fn toggle_editor(keyboard_input: Res<Input<KeyCode>>, mut editor: ResMut<EditorMode>) {
+    if keyboard_input.just_pressed(KeyCode::F2) {
+        editor.enabled = !editor.enabled;
+        info!("Editor enabled: {}", editor.enabled);
+    }
+}
+
+fn select_item(keyboard_input: Res<Input<KeyCode>>, mut editor: ResMut<EditorMode>) {
+    if !editor.enabled {
+        return;
+    }
+
+    let selected = if keyboard_input.just_pressed(KeyCode::Key1) {
+        Some(EditorItem::Obstacle)
+    } else if keyboard_input.just_pressed(KeyCode::Key2) {
+        Some(EditorItem::Npc)
+    } else if keyboard_input.just_pressed(KeyCode::Key3) {
+        Some(EditorItem::Ball)
+    } else {
+        None
+    };
+
+    if let Some(selected) = selected {
+        info!("Editor selected: {:?}", selected);
+        editor.selected = selected;
+    }
+}
+
+fn place(
+    mut commands: Commands,
+    editor: Res<EditorMode>,
+    mut history: ResMut<EditorHistory>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: WorldCameraQuery,
+    rapier_config: Res<RapierConfiguration>,
+    texture_atlas_handle: Res<SimpleFigureTextureAtlasHandle>,
+    animations: Res<SimpleFigureAnimationHandles>,
+    difficulty: Res<Difficulty>,
+    projectile_appearances: Res<ProjectileAppearances>,
+    tuning: Res<Tuning>,
+    mut rejected_balls: ResMut<RejectedBallSpawns>,
+) {
+    if !editor.enabled || !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    if let Some(cursor_world_pos) = cursor_world_position(&windows, &camera_query) {
+        let position = Isometry2::new((cursor_world_pos / rapier_config.scale).into(), 0.0);
+        let entity = match editor.selected {
+            EditorItem::Obstacle => spawn_obstacle(&mut commands, position),
+            EditorItem::Npc => spawn_simple_figure(
+                &mut commands,
+                &texture_atlas_handle,
+                &animations,
+                &tuning,
+                &difficulty,
+                &SimpleFigureSpawnEvent {
+                    position,
+                    ..Default::default()
+                },
+            ),
+            EditorItem::Ball => {
+                let spawn_event = BallSpawnEvent {
+                    position: position.translation.into(),
+                    ..Default::default()
+                };
+                // Spawned directly to record the entity
+                match spawn_ball(
+                    &mut commands,
+                    &projectile_appearances,
+                    tuning.combat.ball_damage,
+                    &spawn_event,
+                    &mut rejected_balls,
+                ) {
+                    Ok(entity) => entity,
+                    Err(_) => return,
+                }
+            }
+        };
+        info!("Editor placed {:?} at {:?}", editor.selected, position);
+        history.0.push(entity);
+    }
+}
+
+fn undo(
+    keyboard_input: Res<Input<KeyCode>>,
+    editor: Res<EditorMode>,
+    entities: &Entities,
+    mut history: ResMut<EditorHistory>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    let ctrl =
+        keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    if !editor.enabled || !ctrl || !keyboard_input.just_pressed(KeyCode::Z) {
+        return;
+    }
+
+    // Skip over anything that was already destroyed during play
+    while let Some(entity) = history.0.pop() {
+        if entities.contains(entity) {
+            info!("Editor undo: removing {:?}", entity);
+            despawn.send(DespawnEvent(entity));
+            return;
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+use crate::simple_figure::SimpleFigureTag;
+
+/// Purely cosmetic effects that only read Transforms, so they look the same
+/// for the player and NPCs
+pub struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DustSettings>()
+            .init_resource::<DustPool>()
+            .add_startup_system(fill_dust_pool)
+            .add_system(track_footsteps)
+            .add_system(kick_up_dust)
+            .add_system(settle_dust);
+    }
+}
+
+pub struct DustSettings {
+    /// Turn off for reduced motion / low effects
+    pub enabled: bool,
+    /// Pixels travelled between puffs
+    pub step_distance: f32,
+    /// Maximum number of puffs alive at once
+    pub pool_size: usize,
+}
+
+impl Default for DustSettings {
+    fn default() -> Self {
+        DustSettings {
+            enabled: true,
+            step_distance: 12.0,
+            pool_size: 64,
+        }
+    }
+}
+
+/// Pre-spawned puff entities that are hidden while not in use, so moving
+/// characters don't churn through spawns and despawns
+#[derive(Default)]
+pub struct DustPool {
+    free: Vec<Entity>,
+}
+
+#[derive(Component)]
+pub struct DustPuff {
+    timer: Timer,
+}
+
+/// Distance travelled since the last puff
+#[derive(Component)]
+pub struct Footsteps {
+    last_position: Vec2,
+    travelled: f32,
+}
+
+const DUST_LIFETIME: f32 = 0.4; // seconds
+const DUST_RADIUS: f32 = 2.0; // px
+const FEET_OFFSET: f32 = -12.0; // px
+
+fn fill_dust_pool(mut commands: Commands, settings: Res<DustSettings>, mut pool: ResMut<DustPool>) {
+    let shape = shapes::Circle {
+        radius: DUST_RADIUS,
+        center: Vec2::ZERO,
+    };
+    for _ in 0..settings.pool_size {
+        let entity = commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shape,
+                DrawMode::Fill(FillMode::color(Color::rgba(0.5, 0.5, 0.5, 0.6))),
+                Transform::default(),
+            ))
+            .insert(Visibility { is_visible: false })
+            .insert(DustPuff {
+                timer: Timer::from_seconds(DUST_LIFETIME, false),
+            })
+            .id();
+        pool.free.push(entity);
+    }
+}
+
+fn track_footsteps(
+    mut commands: Commands,
+    q: Query<(Entity, &Transform), (Added<SimpleFigureTag>, Without<Footsteps>)>,
+) {
+    for (entity, transform) in q.iter() {
+        commands.entity(entity).insert(Footsteps {
+            last_position: transform.translation.truncate(),
+            travelled: 0.0,
+        });
+    }
+}
+
+fn kick_up_dust(
+    settings: Res<DustSettings>,
+    mut pool: ResMut<DustPool>,
+    mut walkers: Query<(&Transform, &mut Footsteps), Without<DustPuff>>,
+    mut puffs: Query<(&mut Transform, &mut Visibility, &mut DustPuff)>,
+) {
+    for (transform, mut footsteps) in walkers.iter_mut() {
+        let position = transform.translation.truncate();
+        footsteps.travelled += position.distance(footsteps.last_position);
+        footsteps.last_position = position;
+
+        if footsteps.travelled < settings.step_distance {
+            continue;
+        }
+        footsteps.travelled = 0.0;
+
+        if !settings.enabled {
+            continue;
+        }
+
+        // If every puff is in use, skip this one rather than growing the pool
+        if let Some(entity) = pool.free.pop() {
+            if let Ok((mut puff_transform, mut visibility, mut puff)) = puffs.get_mut(entity) {
+                *puff_transform = Transform::from_translation(
+                    (position + Vec2::new(0.0, FEET_OFFSET)).extend(1.0),
+                );
+                visibility.is_visible = true;
+                puff.timer.reset();
+            }
+        }
+    }
+}
+
+/// Shrink puffs over their lifetime, then hide them and return them to the pool
+fn settle_dust(
+    time: Res<Time>,
+    mut pool: ResMut<DustPool>,
+    mut puffs: Query<(Entity, &mut Transform, &mut Visibility, &mut DustPuff)>,
+) {
+    for (entity, mut transform, mut visibility, mut puff) in puffs.iter_mut() {
+        if !visibility.is_visible {
+            continue;
+        }
+        puff.timer.tick(time.delta());
+        transform.scale = Vec3::splat(1.0 - puff.timer.percent());
+        if puff.timer.finished() {
+            visibility.is_visible = false;
+            pool.free.push(entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_never_grows_under_heavy_movement() {
+        let mut app = App::new();
+        app.insert_resource(DustSettings {
+            pool_size: 8,
+            ..Default::default()
+        })
+        .insert_resource(Time::default())
+        .add_plugin(EffectsPlugin);
+        let walkers: Vec<Entity> = (0..20)
+            .map(|i| {
+                app.world
+                    .spawn()
+                    .insert(SimpleFigureTag)
+                    .insert(Transform::from_xyz(i as f32 * 100.0, 0.0, 0.0))
+                    .id()
+            })
+            .collect();
+
+        for frame in 1..=30 {
+            for &walker in walkers.iter() {
+                let mut transform = app.world.get_mut::<Transform>(walker).unwrap();
+                transform.translation.y = frame as f32 * 50.0;
+            }
+            app.update();
+
+            let mut puffs = app.world.query::<(&Visibility, &DustPuff)>();
+            let visible = puffs
+                .iter(&app.world)
+                .filter(|(visibility, _)| visibility.is_visible)
+                .count();
+            assert_eq!(puffs.iter(&app.world).count(), 8);
+            let free = app.world.get_resource::<DustPool>().unwrap().free.len();
+            assert_eq!(visible + free, 8);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::ball::BallTag;
+use crate::input::PlayerTag;
+use crate::simple_figure::SimpleFigureTag;
+use crate::tiled::TiledMapComponent;
+
+/// Switches between top-down play and side-view platformer physics.
+/// Maps with a `gravity` property start in side view with that gravity, and
+/// F8 toggles the mode at any time.
+pub struct GravityPlugin;
+
+impl Plugin for GravityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GravityMode>()
+            .add_system(gravity_from_map)
+            .add_system(toggle_gravity)
+            .add_system(apply_world_gravity)
+            .add_system(apply_body_gravity)
+            .add_system(jump.after("movement"));
+    }
+}
+
+/// Which way is down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GravityMode {
+    /// Looking down at the ground, so nothing falls
+    TopDown,
+    /// Looking from the side, with gravity of this many m/s² pulling down
+    SideView(f32),
+}
+
+impl Default for GravityMode {
+    fn default() -> Self {
+        GravityMode::TopDown
+    }
+}
+
+impl GravityMode {
+    pub fn is_side_view(&self) -> bool {
+        matches!(self, GravityMode::SideView(_))
+    }
+}
+
+/// Gravity used when toggling into side view without a map setting
+const DEFAULT_GRAVITY: f32 = 9.81; // m/s²
+
+fn gravity_from_map(
+    mut mode: ResMut<GravityMode>,
+    maps: Query<&TiledMapComponent, Changed<TiledMapComponent>>,
+) {
+    for TiledMapComponent(tiled_map) in maps.iter() {
+        let gravity = match tiled_map.properties.get("gravity") {
+            Some(tiled::PropertyValue::FloatValue(gravity)) => *gravity,
+            Some(tiled::PropertyValue::IntValue(gravity)) => *gravity as f32,
+            Some(other) => {
+                warn!("Map has unexpected gravity value {:?}", other);
+                continue;
+            }
+            None => 0.0,
+        };
+        *mode = if gravity > 0.0 {
+            GravityMode::SideView(gravity)
+        } else {
+            GravityMode::TopDown
+        };
+    }
+}
+
+fn toggle_gravity(keyboard_input: Res<Input<KeyCode>>, mut mode: ResMut<GravityMode>) {
+    if keyboard_input.just_pressed(KeyCode::F8) {
+        *mode = match *mode {
+            GravityMode::TopDown => GravityMode::SideView(DEFAULT_GRAVITY),
+            GravityMode::SideView(_) => GravityMode::TopDown,
+        };
+    }
+}
+
+fn apply_world_gravity(mode: Res<GravityMode>, mut configuration: ResMut<RapierConfiguration>) {
+    if !mode.is_changed() {
+        return;
+    }
+    configuration.gravity = match *mode {
+        GravityMode::TopDown => Vector::zeros(),
+        GravityMode::SideView(gravity) => Vector::new(0.0, -gravity),
+    };
+    info!("Gravity mode: {:?}", *mode);
+}
+
+/// Characters and balls spawn without gravity and unable to rotate, for
+/// top-down play. In side view they fall and tumble.
+fn apply_body_gravity(
+    mode: Res<GravityMode>,
+    mut bodies: QuerySet<(
+        QueryState<
+            (
+                &mut RigidBodyForcesComponent,
+                &mut RigidBodyMassPropsComponent,
+            ),
+            (
+                Or<(With<SimpleFigureTag>, With<BallTag>)>,
+                Or<(
+                    Added<RigidBodyForcesComponent>,
+                    Added<RigidBodyMassPropsComponent>,
+                )>,
+            ),
+        >,
+        QueryState<
+            (
+                &mut RigidBodyForcesComponent,
+                &mut RigidBodyMassPropsComponent,
+            ),
+            Or<(With<SimpleFigureTag>, With<BallTag>)>,
+        >,
+    )>,
+) {
+    let side_view = mode.is_side_view();
+    let apply = |mut forces: Mut<RigidBodyForcesComponent>,
+                 mut mass_props: Mut<RigidBodyMassPropsComponent>| {
+        forces.gravity_scale = if side_view { 1.0 } else { 0.0 };
+        mass_props
+            .flags
+            .set(RigidBodyMassPropsFlags::ROTATION_LOCKED, !side_view);
+    };
+
+    // Reconfigure everything when the mode changes, otherwise only new bodies
+    if mode.is_changed() {
+        for (forces, mass_props) in bodies.q1().iter_mut() {
+            apply(forces, mass_props);
+        }
+    } else if side_view {
+        for (forces, mass_props) in bodies.q0().iter_mut() {
+            apply(forces, mass_props);
+        }
+    }
+}
+
+/// Upward speed of a jump
+const JUMP_SPEED: f32 = 6.0; // m/s
+
+/// How far below the player ground still counts as standing on it
+const GROUND_DISTANCE: f32 = 0.05; // m
+
+/// In side view, up jumps while standing on a wall
+fn jump(
+    mode: Res<GravityMode>,
+    keyboard_input: Res<Input<KeyCode>>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut players: Query<
+        (
+            Entity,
+            &RigidBodyPositionComponent,
+            &ColliderShapeComponent,
+            &mut RigidBodyVelocityComponent,
+        ),
+        With<PlayerTag>,
+    >,
+) {
+    if !mode.is_side_view()
+        || !(keyboard_input.just_pressed(KeyCode::W) || keyboard_input.just_pressed(KeyCode::Up))
+    {
+        return;
+    }
+
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+    for (entity, position, shape, mut velocity) in players.iter_mut() {
+        let grounded = query_pipeline
+            .cast_shape(
+                &collider_set,
+                &position.position,
+                &Vector::new(0.0, -1.0),
+                &***shape,
+                GROUND_DISTANCE,
+                InteractionGroups::new(0b0100, 0b0100),
+                Some(&|handle| handle != entity.handle()),
+            )
+            .is_some();
+        if grounded {
+            velocity.linvel.y = JUMP_SPEED;
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_rapier2d::prelude::*;
+
+use crate::activity::Dormant;
+use crate::health::{Dying, Health};
+use crate::simple_figure::SimpleFigureTag;
+use crate::tiled::TiledMapComponent;
+
+/// Tiles with a `hazard` property hurt characters standing on them, by that
+/// many health points per second
+pub struct HazardPlugin;
+
+impl Plugin for HazardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HazardGrid>()
+            .add_system(build_hazard_grid)
+            .add_system(hazard_damage.before("health_despawner"));
+    }
+}
+
+/// Damage per second of every hazardous map cell
+#[derive(Default)]
+pub struct HazardGrid {
+    /// Size of a cell in physics meters
+    pub cell_size: Vec2,
+    cells: HashMap<(i32, i32), f32>,
+}
+
+impl HazardGrid {
+    /// Damage per second at a position in physics meters
+    pub fn dps_at(&self, position: Vec2) -> f32 {
+        if self.cells.is_empty() {
+            return 0.0;
+        }
+        let cell = (position / self.cell_size).floor();
+        self.cells
+            .get(&(cell.x as i32, cell.y as i32))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Make a cell hazardous. Stacked hazards don't add up, the worst one
+    /// counts.
+    pub fn add_hazard(&mut self, cell: (i32, i32), dps: f32) {
+        let current = self.cells.entry(cell).or_insert(0.0);
+        *current = current.max(dps);
+    }
+}
+
+/// Characters that can stand in hazards unharmed
+#[derive(Component)]
+pub struct HazardImmune;
+
+/// A character currently standing in a hazard, and its color before it was
+/// tinted
+#[derive(Component)]
+pub struct InHazard {
+    base_color: Color,
+    /// Damage taken but not yet applied, since Health is whole points
+    pending_damage: f32,
+}
+
+/// Multiplied into the sprite color of characters standing in a hazard
+const HAZARD_TINT: Color = Color::rgb(1.0, 0.6, 0.6);
+
+fn build_hazard_grid(
+    rc: Res<RapierConfiguration>,
+    mut grid: ResMut<HazardGrid>,
+    maps: Query<&TiledMapComponent, Changed<TiledMapComponent>>,
+) {
+    for TiledMapComponent(tiled_map) in maps.iter() {
+        // Tile ids are local to their tileset, so key them by tileset too
+        let mut hazard_tiles = HashMap::default();
+        for (tileset_index, tileset) in tiled_map.tilesets().iter().enumerate() {
+            for (id, tile) in tileset.tiles() {
+                let dps = match tile.properties.get("hazard") {
+                    Some(tiled::PropertyValue::FloatValue(dps)) => *dps,
+                    Some(tiled::PropertyValue::IntValue(dps)) => *dps as f32,
+                    Some(other) => {
+                        warn!(
+                            "Tile {} of tileset {} has unexpected hazard value {:?}",
+                            id, tileset.name, other
+                        );
+                        continue;
+                    }
+                    None => continue,
+                };
+                hazard_tiles.insert((tileset_index, id), dps);
+            }
+        }
+
+        grid.cell_size =
+            Vec2::new(tiled_map.tile_width as f32, tiled_map.tile_height as f32) / rc.scale;
+        grid.cells.clear();
+        if hazard_tiles.is_empty() {
+            continue;
+        }
+
+        for layer in tiled_map.layers() {
+            if let tiled::LayerType::Tiles(tiled::TileLayer::Finite(data)) = layer.layer_type() {
+                for x in 0..tiled_map.width {
+                    for y in 0..tiled_map.height {
+                        let dps = match data.get_tile(x as i32, y as i32) {
+                            Some(tile) => {
+                                match hazard_tiles.get(&(tile.tileset_index(), tile.id())) {
+                                    Some(dps) => *dps,
+                                    None => continue,
+                                }
+                            }
+                            None => continue,
+                        };
+                        // Tiled rows go down, but world rows go up
+                        let row = if tiled_map.orientation == tiled::Orientation::Orthogonal {
+                            tiled_map.height - 1 - y
+                        } else {
+                            y
+                        };
+                        grid.add_hazard((x as i32, row as i32), dps);
+                    }
+                }
+            }
+        }
+        info!("Map has {} hazard cells", grid.cells.len());
+    }
+}
+
+/// Add a frame's worth of hazard damage to what is pending, and take the
+/// whole points out to apply now
+fn accumulate_damage(pending_damage: &mut f32, dps: f32, delta_seconds: f32) -> i32 {
+    *pending_damage += dps * delta_seconds;
+    let damage = pending_damage.floor();
+    *pending_damage -= damage;
+    damage as i32
+}
+
+fn hazard_damage(
+    mut commands: Commands,
+    time: Res<Time>,
+    grid: Res<HazardGrid>,
+    mut characters: Query<
+        (
+            Entity,
+            &RigidBodyPositionComponent,
+            &mut Health,
+            &mut TextureAtlasSprite,
+            Option<&mut InHazard>,
+        ),
+        (
+            With<SimpleFigureTag>,
+            Without<HazardImmune>,
+            Without<Dying>,
+            Without<Dormant>,
+        ),
+    >,
+) {
+    for (entity, position, mut health, mut sprite, in_hazard) in characters.iter_mut() {
+        let dps = grid.dps_at(position.position.translation.into());
+        match (dps > 0.0, in_hazard) {
+            (true, Some(mut in_hazard)) => {
+                health.current -=
+                    accumulate_damage(&mut in_hazard.pending_damage, dps, time.delta_seconds());
+            }
+            (true, None) => {
+                let mut pending_damage = 0.0;
+                health.current -= accumulate_damage(&mut pending_damage, dps, time.delta_seconds());
+                commands.entity(entity).insert(InHazard {
+                    base_color: sprite.color,
+                    pending_damage,
+                });
+                let base = sprite.color;
+                sprite.color = Color::rgba(
+                    base.r() * HAZARD_TINT.r(),
+                    base.g() * HAZARD_TINT.g(),
+                    base.b() * HAZARD_TINT.b(),
+                    base.a(),
+                );
+            }
+            (false, Some(in_hazard)) => {
+                sprite.color = in_hazard.base_color;
+                commands.entity(entity).remove::<InHazard>();
+            }
+            (false, None) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dps_is_looked_up_by_cell() {
+        let mut grid = HazardGrid {
+            cell_size: Vec2::new(0.5, 1.0),
+            ..Default::default()
+        };
+        assert_eq!(grid.dps_at(Vec2::new(0.7, 2.5)), 0.0);
+        grid.add_hazard((1, 2), 2.0);
+        grid.add_hazard((1, 2), 1.0);
+        assert_eq!(grid.dps_at(Vec2::new(0.7, 2.5)), 2.0);
+        assert_eq!(grid.dps_at(Vec2::new(1.1, 2.5)), 0.0);
+        assert_eq!(grid.dps_at(Vec2::new(-0.2, 2.5)), 0.0);
+    }
+
+    #[test]
+    fn standing_in_two_dps_for_three_seconds_costs_six_health() {
+        let mut pending_damage = 0.0;
+        let mut health = 10;
+        // Frames of a quarter second, which add up exactly
+        for _ in 0..12 {
+            health -= accumulate_damage(&mut pending_damage, 2.0, 0.25);
+        }
+        assert_eq!(health, 4);
+        assert_eq!(pending_damage, 0.0);
+    }
+
+    #[test]
+    fn fractional_damage_carries_over() {
+        let mut pending_damage = 0.0;
+        assert_eq!(accumulate_damage(&mut pending_damage, 2.0, 0.25), 0);
+        assert_eq!(accumulate_damage(&mut pending_damage, 2.0, 0.25), 1);
+        assert_eq!(accumulate_damage(&mut pending_damage, 2.0, 0.25), 0);
+        assert_eq!(pending_damage, 0.5);
+    }
+}
@@ -1,13 +1,23 @@
 use bevy::prelude::*;
+use bevy::utils::HashMap;
 use bevy_rapier2d::prelude::*;
 
+use crate::ai::{Allegiance, Dodge};
+use crate::ball::Owner;
 use crate::ecs::DespawnEvent;
+use crate::input::{MoveAction, PlayerTag};
+use crate::pathfinding::GoalPosition;
+use crate::pathfollowing::Carrot;
 
 pub struct HealthPlugin;
 
 impl Plugin for HealthPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(damage).add_system(health_despawner);
+        app.add_event::<DeathEvent>()
+            .init_resource::<DamageDedup>()
+            .add_system(damage.label("damage"))
+            .add_system(health_despawner.label("health_despawner").after("damage"))
+            .add_system(fade_out_dying.after("health_despawner"));
     }
 }
 
@@ -28,31 +38,368 @@ pub struct CollisionDamage {
     pub damage: i32,
 }
 
+/// Damage a damager takes itself when it hits something with Health.
+/// Applied at most once per frame, no matter how many targets it hit, so a
+/// ball hitting two characters at once damages both before it dies.
+/// Contacts with walls and other things without Health don't count.
+#[derive(Component)]
+pub struct CollisionSelfDamage {
+    pub damage: i32,
+}
+
+/// Contact pairs that already dealt damage recently. A single physical
+/// collision can start several contacts in quick succession, e.g. a fast ball
+/// re-touching its target, and should only deal damage once.
+pub struct DamageDedup {
+    /// How long each pair is remembered after it dealt damage
+    pub window: f64, // seconds
+    /// When each pair last dealt damage, in seconds since startup
+    recent: HashMap<(Entity, Entity), f64>,
+}
+
+impl Default for DamageDedup {
+    fn default() -> Self {
+        DamageDedup {
+            window: 0.1,
+            recent: HashMap::default(),
+        }
+    }
+}
+
+impl DamageDedup {
+    /// Whether the pair may deal damage at now, remembering it if so
+    fn admit(&mut self, damager: Entity, target: Entity, now: f64) -> bool {
+        let window = self.window;
+        // Each pair is forgotten on its own once its window has passed
+        self.recent.retain(|_, hit_at| now - *hit_at < window);
+        if self.recent.contains_key(&(damager, target)) {
+            return false;
+        }
+        self.recent.insert((damager, target), now);
+        true
+    }
+}
+
+/// Sent when an entity's health runs out, before it is despawned
+pub struct DeathEvent(pub Entity);
+
+/// How long a dead entity takes to fade out before it is despawned
+const DEATH_FADE_DURATION: f32 = 0.5; // seconds
+
+/// Marks an entity that has died and is fading out. It no longer collides,
+/// moves or takes part in AI.
+#[derive(Component)]
+pub struct Dying {
+    pub timer: Timer,
+}
+
 fn health_despawner(
-    q: Query<(Entity, &Health), Changed<Health>>,
-    mut despawn: EventWriter<DespawnEvent>,
+    mut commands: Commands,
+    mut q: Query<
+        (Entity, &Health, Option<&mut RigidBodyVelocityComponent>),
+        (Changed<Health>, Without<Dying>),
+    >,
+    mut death: EventWriter<DeathEvent>,
 ) {
-    for (entity, health) in q.iter() {
+    for (entity, health, velocity) in q.iter_mut() {
         if health.current <= 0 {
+            death.send(DeathEvent(entity));
+            if let Some(mut velocity) = velocity {
+                velocity.linvel = Vector::zeros();
+                velocity.angvel = 0.0;
+            }
+            commands
+                .entity(entity)
+                .remove_bundle::<ColliderBundle>()
+                .remove::<MoveAction>()
+                .remove::<PlayerTag>()
+                .remove::<Allegiance>()
+                .remove::<GoalPosition>()
+                .remove::<Carrot>()
+                .remove::<Dodge>()
+                .insert(Dying {
+                    timer: Timer::from_seconds(DEATH_FADE_DURATION, false),
+                });
+        }
+    }
+}
+
+/// Fade dying sprites out, then despawn them
+fn fade_out_dying(
+    time: Res<Time>,
+    mut q: Query<(
+        Entity,
+        &mut Dying,
+        Option<&mut Sprite>,
+        Option<&mut TextureAtlasSprite>,
+    )>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    for (entity, mut dying, sprite, atlas_sprite) in q.iter_mut() {
+        // Despawned the same frame, so this doesn't repeat
+        if dying.timer.tick(time.delta()).finished() {
             despawn.send(DespawnEvent(entity));
         }
+        let alpha = 1.0 - dying.timer.percent();
+        if let Some(mut sprite) = sprite {
+            sprite.color.set_a(alpha);
+        }
+        if let Some(mut sprite) = atlas_sprite {
+            sprite.color.set_a(alpha);
+        }
+    }
+}
+
+/// Whether damager was fired by target itself or by someone on the same side.
+/// Neutral characters can be hurt by anyone.
+fn is_friendly_fire(
+    damager: Entity,
+    target: Entity,
+    owner_query: &Query<&Owner>,
+    allegiance_query: &Query<&Allegiance>,
+) -> bool {
+    if let Ok(Owner(owner)) = owner_query.get(damager) {
+        if *owner == target {
+            return true;
+        }
+        if let (Ok(owner_allegiance), Ok(target_allegiance)) =
+            (allegiance_query.get(*owner), allegiance_query.get(target))
+        {
+            return *owner_allegiance != Allegiance::Neutral
+                && owner_allegiance == target_allegiance;
+        }
     }
+    false
 }
 
 fn damage(
-    damager_query: Query<&CollisionDamage>,
+    damager_query: Query<(Option<&CollisionDamage>, Option<&CollisionSelfDamage>)>,
     mut health_query: Query<&mut Health>,
+    owner_query: Query<&Owner>,
+    allegiance_query: Query<&Allegiance>,
     mut contact_events: EventReader<ContactEvent>,
+    time: Res<Time>,
+    mut dedup: ResMut<DamageDedup>,
 ) {
+    let now = time.seconds_since_startup();
+
+    // Gather every contact started this frame per damager before applying any
+    // damage, so the outcome doesn't depend on the order of the events
+    let mut contacts: HashMap<Entity, Vec<Entity>> = HashMap::default();
     for contact_event in contact_events.iter() {
         if let ContactEvent::Started(c1, c2) = contact_event {
-            for (damager, damageable) in [(c1, c2), (c2, c1)] {
-                if let Ok(CollisionDamage { damage }) = damager_query.get(damager.entity()) {
-                    if let Ok(mut health) = health_query.get_mut(damageable.entity()) {
-                        health.current -= damage;
-                    }
+            for (damager, other) in [(c1.entity(), c2.entity()), (c2.entity(), c1.entity())] {
+                if let Ok((None, None)) | Err(_) = damager_query.get(damager) {
+                    continue;
+                }
+                if is_friendly_fire(damager, other, &owner_query, &allegiance_query) {
+                    continue;
+                }
+                if !dedup.admit(damager, other, now) {
+                    continue;
+                }
+                let targets = contacts.entry(damager).or_insert_with(Vec::new);
+                if !targets.contains(&other) {
+                    targets.push(other);
+                }
+            }
+        }
+    }
+
+    // Every target is damaged before the damager itself, and the despawner
+    // runs after this system, so the order of targets doesn't matter
+    for (damager, targets) in contacts {
+        let (collision_damage, self_damage) = damager_query.get(damager).unwrap();
+
+        let mut hit_any = false;
+        for target in targets.iter() {
+            if let Ok(mut health) = health_query.get_mut(*target) {
+                hit_any = true;
+                if let Some(CollisionDamage { damage }) = collision_damage {
+                    health.current -= damage;
                 }
             }
         }
+
+        if let (true, Some(CollisionSelfDamage { damage })) = (hit_any, self_damage) {
+            if let Ok(mut health) = health_query.get_mut(damager) {
+                health.current -= damage;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::DespawnPlugin;
+    use std::time::Duration;
+
+    /// App running only the damage system, fed with hand-made contact events
+    fn damage_app() -> App {
+        let mut app = App::new();
+        app.add_event::<ContactEvent>()
+            .insert_resource(Time::default())
+            .init_resource::<DamageDedup>()
+            .add_system(damage);
+        app
+    }
+
+    fn started(a: Entity, b: Entity) -> ContactEvent {
+        ContactEvent::Started(a.handle(), b.handle())
+    }
+
+    fn health(app: &App, entity: Entity) -> i32 {
+        app.world.get::<Health>(entity).unwrap().current
+    }
+
+    fn spawn_ball(app: &mut App) -> Entity {
+        app.world
+            .spawn()
+            .insert(CollisionDamage { damage: 1 })
+            .insert(CollisionSelfDamage { damage: 1 })
+            .insert(Health::from_max(1))
+            .id()
+    }
+
+    /// Ball hits two characters in the same frame, with the contact events
+    /// in the given order. Returns the health of the ball and both targets.
+    fn hit_two(swap: bool) -> (i32, i32, i32) {
+        let mut app = damage_app();
+        let ball = spawn_ball(&mut app);
+        let a = app.world.spawn().insert(Health::from_max(5)).id();
+        let b = app.world.spawn().insert(Health::from_max(5)).id();
+
+        let mut events = vec![started(ball, a), started(b, ball)];
+        if swap {
+            events.reverse();
+        }
+        for event in events {
+            app.world
+                .get_resource_mut::<Events<ContactEvent>>()
+                .unwrap()
+                .send(event);
+        }
+        app.update();
+
+        (health(&app, ball), health(&app, a), health(&app, b))
+    }
+
+    #[test]
+    fn both_targets_are_damaged_and_self_damage_applies_once() {
+        assert_eq!(hit_two(false), (0, 4, 4));
+    }
+
+    #[test]
+    fn contact_order_does_not_change_the_outcome() {
+        assert_eq!(hit_two(false), hit_two(true));
+    }
+
+    #[test]
+    fn walls_do_not_use_up_balls() {
+        let mut app = damage_app();
+        let ball = spawn_ball(&mut app);
+        let wall = app.world.spawn().id();
+        let target = app.world.spawn().insert(Health::from_max(5)).id();
+        app.world
+            .get_resource_mut::<Events<ContactEvent>>()
+            .unwrap()
+            .send(started(ball, wall));
+        app.update();
+        assert_eq!(health(&app, ball), 1);
+
+        // A wall and a character in the same frame still count once
+        for event in [started(ball, wall), started(ball, target)] {
+            app.world
+                .get_resource_mut::<Events<ContactEvent>>()
+                .unwrap()
+                .send(event);
+        }
+        app.update();
+        assert_eq!(health(&app, ball), 0);
+        assert_eq!(health(&app, target), 4);
+    }
+
+    #[test]
+    fn repeated_contacts_within_the_window_damage_once() {
+        let mut app = damage_app();
+        let spike = app.world.spawn().insert(CollisionDamage { damage: 1 }).id();
+        let target = app.world.spawn().insert(Health::from_max(5)).id();
+        // Time doesn't advance in the test app, so both frames fall in one
+        // window
+        for _ in 0..2 {
+            app.world
+                .get_resource_mut::<Events<ContactEvent>>()
+                .unwrap()
+                .send(started(spike, target));
+            app.update();
+        }
+        assert_eq!(health(&app, target), 4);
+    }
+
+    #[test]
+    fn pairs_expire_individually() {
+        let mut dedup = DamageDedup::default();
+        let mut world = World::new();
+        let (spike, a, b) = (world.spawn().id(), world.spawn().id(), world.spawn().id());
+
+        assert!(dedup.admit(spike, a, 0.0));
+        // Inside a's window, while b's starts
+        assert!(!dedup.admit(spike, a, 0.06));
+        assert!(dedup.admit(spike, b, 0.06));
+        // a's window is over, b's is not
+        assert!(dedup.admit(spike, a, 0.12));
+        assert!(!dedup.admit(spike, b, 0.12));
+        // Now b's is over too
+        assert!(dedup.admit(spike, b, 0.2));
+    }
+
+    #[test]
+    fn dying_characters_lose_collider_and_input_then_fade_out() {
+        let mut app = App::new();
+        app.add_plugin(DespawnPlugin)
+            .add_event::<DeathEvent>()
+            .insert_resource(Time::default())
+            .add_system(health_despawner.label("health_despawner"))
+            .add_system(fade_out_dying.after("health_despawner"));
+        let character = app
+            .world
+            .spawn()
+            .insert_bundle(ColliderBundle::default())
+            .insert(MoveAction::default())
+            .insert(Health::from_max(3))
+            .insert(Sprite::default())
+            .id();
+        app.update();
+        assert!(app.world.get::<Dying>(character).is_none());
+
+        app.world.get_mut::<Health>(character).unwrap().current = 0;
+        app.update();
+        assert!(app.world.get::<Dying>(character).is_some());
+        assert!(app.world.get::<ColliderShapeComponent>(character).is_none());
+        assert!(app.world.get::<MoveAction>(character).is_none());
+        let deaths = app.world.get_resource::<Events<DeathEvent>>().unwrap();
+        assert_eq!(deaths.get_reader().iter(deaths).count(), 1);
+
+        // Almost faded out
+        let almost = Duration::from_secs_f32(DEATH_FADE_DURATION * 0.9);
+        app.world
+            .get_mut::<Dying>(character)
+            .unwrap()
+            .timer
+            .tick(almost);
+        app.update();
+        assert!(app.world.get_entity(character).is_some());
+        assert!(app.world.get::<Sprite>(character).unwrap().color.a() < 0.2);
+
+        // The rest of the fade
+        let rest = Duration::from_secs_f32(DEATH_FADE_DURATION * 0.2);
+        app.world
+            .get_mut::<Dying>(character)
+            .unwrap()
+            .timer
+            .tick(rest);
+        app.update();
+        assert!(app.world.get_entity(character).is_none());
     }
 }
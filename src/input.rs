@@ -1,21 +1,54 @@
-use crate::ball::BallSpawnEvent;
+use crate::activity::Dormant;
+use crate::ai::{Allegiance, ShootCooldown};
+use crate::aim::{
+    draw_trajectory_preview, line_of_sight, update_target_lock, AimAssist, TargetLock,
+    TrajectoryPreview,
+};
+use crate::assets::assets_ready;
+use crate::ball::{BallSpawnEvent, ProjectileKind};
+use crate::editor::EditorMode;
+use crate::gravity::GravityMode;
+use crate::simple_figure::SimpleFigureTag;
+use crate::tuning::Tuning;
 use bevy::math::Vec3Swizzles;
 use bevy::math::Vec4Swizzles;
-use bevy::render::camera::Camera;
+use bevy::render::camera::{Camera, CameraPlugin, OrthographicProjection};
 use bevy::{
     input::{keyboard::KeyCode, Input},
     prelude::*,
 };
 use bevy_rapier2d::prelude::*;
-use nalgebra::Isometry2;
 
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(keyboard)
-            .add_system(mouse_aim)
-            .add_system(movement);
+        // MoveActions are written by keyboard (and pathfollowing for NPCs) and
+        // turned into velocities before the physics step in the same frame
+        app.init_resource::<AimAssist>()
+            .init_resource::<SelectedProjectile>()
+            .init_resource::<TrajectoryPreview>()
+            .init_resource::<KeyboardShoot>()
+            .init_resource::<TargetLock>()
+            .add_system(select_projectile)
+            .add_system(keyboard.label("keyboard"))
+            .add_system(mouse_aim.label("mouse_aim").with_run_criteria(assets_ready))
+            .add_system(
+                keyboard_shoot
+                    .after("keyboard")
+                    .after("mouse_aim")
+                    .with_run_criteria(assets_ready),
+            )
+            .add_system(
+                update_target_lock
+                    .label("target_lock")
+                    .before("mouse_aim")
+                    .after("keyboard"),
+            )
+            .add_system(draw_trajectory_preview.after("target_lock"))
+            // Update runs before the physics step stage, so movement always
+            // applies this frame
+            .add_system(movement.label("movement").after("keyboard"));
     }
 }
 
@@ -25,15 +58,47 @@ pub struct MoveAction {
     pub desired_velocity: Vec2,
 }
 
+/// Speed in m/s of a full MoveAction, for entities that don't use the default
+#[derive(Component)]
+pub struct MoveSpeed(pub f32);
+
+const DEFAULT_MOVE_SPEED: f32 = 5.0; // m/s
+
 /// Tag that marks entity as playable
 #[derive(Component)]
 pub struct PlayerTag;
 
+/// Direction a player last moved in, normalized
+#[derive(Component)]
+pub struct Facing(pub Vec2);
+
+impl Default for Facing {
+    fn default() -> Self {
+        // The sprite starts out facing the camera, i.e. down
+        Facing(Vec2::new(0.0, -1.0))
+    }
+}
+
+/// Fire toward the facing direction with a key, for playing without a mouse
+pub struct KeyboardShoot {
+    pub enabled: bool,
+    pub key: KeyCode,
+}
+
+impl Default for KeyboardShoot {
+    fn default() -> Self {
+        KeyboardShoot {
+            enabled: true,
+            key: KeyCode::Space,
+        }
+    }
+}
+
 fn keyboard(
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<&mut MoveAction, With<PlayerTag>>,
+    mut query: Query<(&mut MoveAction, Option<&mut Facing>), With<PlayerTag>>,
 ) {
-    for mut move_action in query.iter_mut() {
+    for (mut move_action, facing) in query.iter_mut() {
         let mut desired_velocity = Vec2::splat(0.0);
 
         if keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up) {
@@ -57,55 +122,264 @@ fn keyboard(
         } else {
             desired_velocity
         };
+
+        if let Some(mut facing) = facing {
+            if move_action.desired_velocity != Vec2::ZERO {
+                facing.0 = move_action.desired_velocity;
+            }
+        }
+    }
+}
+
+/// The projectile the player fires, cycled with Q
+#[derive(Default)]
+pub struct SelectedProjectile(pub ProjectileKind);
+
+fn select_projectile(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut selected: ResMut<SelectedProjectile>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Q) {
+        selected.0 = selected.0.next();
+        info!("Selected projectile: {:?}", selected.0);
     }
 }
 
+/// Convert a cursor position in window pixels into world coordinates.
+///
+/// The default orthographic projection is in pixels from the center of the
+/// window, so this only depends on the current window size and the camera,
+/// and stays correct after the window is resized.
+/// https://bevy-cheatbook.github.io/cookbook/cursor2world.html
+pub fn cursor_to_world(
+    cursor_pos: Vec2,
+    window_size: Vec2,
+    camera_transform: &GlobalTransform,
+    projection_scale: f32,
+) -> Vec2 {
+    // undo the translation to the window center, then the projection scale
+    let p = (cursor_pos - window_size / 2.0) * projection_scale;
+
+    // apply the camera transform
+    (camera_transform.compute_matrix() * p.extend(0.0).extend(1.0)).xy()
+}
+
+/// Cameras that can be used to map the cursor into the world
+pub type WorldCameraQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Camera,
+        &'static GlobalTransform,
+        &'static OrthographicProjection,
+    ),
+>;
+
+/// World position of the cursor in the primary window, in pixels.
+///
+/// Uses the 2D world camera, ignoring any UI cameras.
+pub fn cursor_world_position(windows: &Windows, camera_query: &WorldCameraQuery) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let cursor_pos = window.cursor_position()?;
+    let (_, camera_transform, projection) = camera_query
+        .iter()
+        .find(|(camera, _, _)| camera.name.as_deref() == Some(CameraPlugin::CAMERA_2D))?;
+
+    let size = Vec2::new(window.width() as f32, window.height() as f32);
+    Some(cursor_to_world(
+        cursor_pos,
+        size,
+        camera_transform,
+        projection.scale,
+    ))
+}
+
 fn mouse_aim(
+    mut commands: Commands,
+    time: Res<Time>,
+    tuning: Res<Tuning>,
     buttons: Res<Input<MouseButton>>,
     windows: Res<Windows>,
     rapier_config: Res<RapierConfiguration>,
-    player_query: Query<&GlobalTransform, With<PlayerTag>>,
-    camera_query: Query<&Transform, With<Camera>>,
+    aim_assist: Res<AimAssist>,
+    target_lock: Res<TargetLock>,
+    selected_projectile: Res<SelectedProjectile>,
+    mut player_query: Query<
+        (Entity, &GlobalTransform, Option<&mut ShootCooldown>),
+        With<PlayerTag>,
+    >,
+    target_query: Query<
+        (Entity, &RigidBodyPositionComponent, &Allegiance),
+        (With<SimpleFigureTag>, Without<PlayerTag>),
+    >,
+    camera_query: WorldCameraQuery,
+    editor: Res<EditorMode>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    mut ball_spawn_event: EventWriter<BallSpawnEvent>,
+) {
+    for (_, _, cooldown) in player_query.iter_mut() {
+        if let Some(mut cooldown) = cooldown {
+            cooldown.0.tick(time.delta());
+        }
+    }
+
+    // Clicks place entities while editing
+    if editor.enabled || !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let cursor_world_pos = match cursor_world_position(&windows, &camera_query) {
+        Some(cursor_world_pos) => cursor_world_pos,
+        None => return,
+    };
+
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (player_entity, player_tf, cooldown) in player_query.iter() {
+        if let Some(cooldown) = cooldown {
+            if !cooldown.0.finished() {
+                continue;
+            }
+        }
+
+        let player_pos = (player_tf.translation / rapier_config.scale).xy();
+        let cursor_real_pos = cursor_world_pos / rapier_config.scale;
+        let direction = (cursor_real_pos - player_pos).normalize_or_zero();
+
+        // A locked target overrides the cursor and aim assist
+        let direction = match target_lock.target_position() {
+            Some(target_pos) => (target_pos - player_pos).normalize_or_zero(),
+            None => aim_assist.apply(
+                player_pos,
+                direction,
+                target_query
+                    .iter()
+                    .filter(|(_, _, allegiance)| **allegiance == Allegiance::Hostile)
+                    .map(|(entity, position, _)| (entity, position.position.translation.into())),
+                |target, target_pos| {
+                    line_of_sight(
+                        &query_pipeline,
+                        &collider_set,
+                        player_entity,
+                        player_pos,
+                        target,
+                        target_pos,
+                    )
+                },
+            ),
+        };
+
+        info!("goal_position: {:?}", cursor_real_pos);
+
+        ball_spawn_event.send(BallSpawnEvent::shot(
+            player_pos,
+            direction,
+            tuning.combat.shot_speed,
+            selected_projectile.0,
+            player_entity,
+        ));
+        commands
+            .entity(player_entity)
+            .insert(ShootCooldown(Timer::from_seconds(
+                tuning.combat.shoot_cooldown,
+                false,
+            )));
+    }
+}
+
+/// Shoot in the direction the player is facing, bypassing the cursor.
+/// Shares the cooldown with mouse aiming, which also ticks it.
+fn keyboard_shoot(
+    mut commands: Commands,
+    keyboard_shoot: Res<KeyboardShoot>,
+    keyboard_input: Res<Input<KeyCode>>,
+    editor: Res<EditorMode>,
+    tuning: Res<Tuning>,
+    rapier_config: Res<RapierConfiguration>,
+    target_lock: Res<TargetLock>,
+    selected_projectile: Res<SelectedProjectile>,
+    player_query: Query<
+        (Entity, &GlobalTransform, &Facing, Option<&ShootCooldown>),
+        With<PlayerTag>,
+    >,
     mut ball_spawn_event: EventWriter<BallSpawnEvent>,
 ) {
-    for player_tf in player_query.iter() {
-        if let Some(window) = windows.get_primary() {
-            if let Some(cursor_pos) = window.cursor_position() {
-                if buttons.just_pressed(MouseButton::Left) {
-                    let size = Vec2::new(window.width() as f32, window.height() as f32);
-
-                    // https://bevy-cheatbook.github.io/cookbook/cursor2world.html
-                    // the default orthographic projection is in pixels from the center;
-                    // just undo the translation
-                    let p = cursor_pos - size / 2.0;
-
-                    // assuming there is exactly one main camera entity, so this is OK
-                    let camera_transform = camera_query.single();
-
-                    // apply the camera transform
-                    let cursor_world_pos =
-                        camera_transform.compute_matrix() * p.extend(0.0).extend(1.0);
-
-                    let player_pos = (player_tf.translation / rapier_config.scale).xy();
-                    let cursor_real_pos = (cursor_world_pos / rapier_config.scale).xy();
-                    let direction = (cursor_real_pos - player_pos).normalize_or_zero();
-
-                    info!("goal_position: {:?}", cursor_real_pos);
-
-                    ball_spawn_event.send(BallSpawnEvent {
-                        position: Isometry2::new((player_pos + direction * 1.0).into(), 0.0),
-                        velocity: direction * 10.0,
-                        ..Default::default()
-                    });
-                }
+    if !keyboard_shoot.enabled || editor.enabled || !keyboard_input.just_pressed(keyboard_shoot.key)
+    {
+        return;
+    }
+
+    for (player_entity, player_tf, Facing(direction), cooldown) in player_query.iter() {
+        if let Some(cooldown) = cooldown {
+            if !cooldown.0.finished() {
+                continue;
             }
         }
+
+        let player_pos = (player_tf.translation / rapier_config.scale).xy();
+        let direction = match target_lock.target_position() {
+            Some(target_pos) => (target_pos - player_pos).normalize_or_zero(),
+            None => *direction,
+        };
+        ball_spawn_event.send(BallSpawnEvent::shot(
+            player_pos,
+            direction,
+            tuning.combat.shot_speed,
+            selected_projectile.0,
+            player_entity,
+        ));
+        commands
+            .entity(player_entity)
+            .insert(ShootCooldown(Timer::from_seconds(
+                tuning.combat.shoot_cooldown,
+                false,
+            )));
     }
 }
 
-fn movement(mut query: Query<(&MoveAction, &mut RigidBodyVelocityComponent)>) {
-    for (move_action, mut velocity) in query.iter_mut() {
+fn movement(
+    gravity_mode: Res<GravityMode>,
+    mut query: Query<
+        (
+            &MoveAction,
+            Option<&MoveSpeed>,
+            &mut RigidBodyVelocityComponent,
+        ),
+        Without<Dormant>,
+    >,
+) {
+    for (move_action, move_speed, mut velocity) in query.iter_mut() {
+        let speed = move_speed.map_or(DEFAULT_MOVE_SPEED, |MoveSpeed(speed)| *speed);
         // TODO: use forces or impulses rather than setting velocity
-        velocity.linvel = (move_action.desired_velocity * 5.0).into();
+        if gravity_mode.is_side_view() {
+            // Leave falling to gravity
+            velocity.linvel.x = move_action.desired_velocity.x * speed;
+        } else {
+            velocity.linvel = (move_action.desired_velocity * speed).into();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_maps_to_world_at_any_window_size() {
+        let camera = GlobalTransform::from_translation(Vec3::new(100.0, 50.0, 999.0));
+        for window_size in [Vec2::new(800.0, 600.0), Vec2::new(1920.0, 1080.0)] {
+            // The window center is wherever the camera is
+            let center = cursor_to_world(window_size / 2.0, window_size, &camera, 1.0);
+            assert_eq!(center, Vec2::new(100.0, 50.0));
+
+            // The bottom left corner is half a window away
+            let corner = cursor_to_world(Vec2::ZERO, window_size, &camera, 1.0);
+            assert_eq!(corner, Vec2::new(100.0, 50.0) - window_size / 2.0);
+
+            // A zoomed out camera sees more of the world per pixel
+            let zoomed = cursor_to_world(Vec2::ZERO, window_size, &camera, 2.0);
+            assert_eq!(zoomed, Vec2::new(100.0, 50.0) - window_size);
+        }
     }
 }
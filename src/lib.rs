@@ -4,44 +4,78 @@ use bevy::prelude::*;
 use bevy_prototype_lyon::plugin::ShapePlugin;
 use bevy_rapier2d::prelude::*;
 
+mod activity;
 mod ai;
+mod aim;
+mod assets;
 mod ball;
 mod camera;
+mod debug;
 mod ecs;
+mod editor;
+mod effects;
+mod gravity;
+mod hazard;
 mod health;
 mod input;
 pub mod obstacle;
 mod pathfinding;
 mod pathfollowing;
+mod pickup;
+mod sanity;
+mod settings;
 pub mod simple_figure;
 pub mod tiled;
+mod tuning;
 
 use crate::pathfinding::PathfindingPlugin;
+use activity::ActivityPlugin;
 use ai::AiPlugin;
+use assets::AssetPreloadPlugin;
 use ball::BallPlugin;
 use camera::CameraPlugin;
+use debug::DebugPlugin;
 use ecs::DespawnPlugin;
+use editor::EditorPlugin;
+use effects::EffectsPlugin;
+use gravity::GravityPlugin;
+use hazard::HazardPlugin;
 use health::HealthPlugin;
 use input::InputPlugin;
 use pathfollowing::PathfollowingPlugin;
+use pickup::PickupPlugin;
+use sanity::SanityPlugin;
+use settings::SettingsPlugin;
 use simple_figure::SimpleFigurePlugin;
+use tuning::TuningPlugin;
 pub struct SandboxPlugins;
 
 impl PluginGroup for SandboxPlugins {
     fn build(&mut self, group: &mut PluginGroupBuilder) {
         group.add(AnimationPlugin);
         group.add(RapierPhysicsPlugin::<NoUserData>::default());
+        group.add(GravityPlugin);
         group.add(DefaultResources);
+        group.add(SettingsPlugin);
+        group.add(TuningPlugin);
+        group.add(AssetPreloadPlugin);
         group.add(InputPlugin);
         group.add(SimpleFigurePlugin);
         group.add(CameraPlugin);
         group.add(BallPlugin);
         group.add(HealthPlugin);
+        group.add(HazardPlugin);
         group.add(PathfindingPlugin);
         group.add(ShapePlugin);
         group.add(PathfollowingPlugin);
         group.add(AiPlugin);
+        group.add(ActivityPlugin);
         group.add(DespawnPlugin);
+        group.add(SanityPlugin);
+        group.add(EditorPlugin);
+        group.add(PickupPlugin);
+        group.add(DebugPlugin);
+        group.add(EffectsPlugin);
     }
 }
 
@@ -54,6 +88,22 @@ impl Plugin for DefaultResources {
             0xF9 as f32 / 255.0,
             0xFF as f32 / 255.0,
         )))
-        .insert_resource(Msaa::default());
+        .insert_resource(Msaa::default())
+        .add_system(cycle_msaa);
+    }
+}
+
+/// Sample counts every renderer supports, from fastest to smoothest
+const MSAA_SAMPLES: [u32; 2] = [1, 4];
+
+/// Step through the supported anti-aliasing levels with F7
+fn cycle_msaa(keyboard_input: Res<Input<KeyCode>>, mut msaa: ResMut<Msaa>) {
+    if keyboard_input.just_pressed(KeyCode::F7) {
+        let index = MSAA_SAMPLES
+            .iter()
+            .position(|samples| *samples == msaa.samples)
+            .map_or(0, |index| (index + 1) % MSAA_SAMPLES.len());
+        msaa.samples = MSAA_SAMPLES[index];
+        info!("MSAA samples: {}", msaa.samples);
     }
 }
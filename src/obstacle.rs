@@ -11,13 +11,19 @@ impl Plugin for ObstaclePlugin {
 }
 
 pub fn spawn(mut commands: Commands) {
+    spawn_obstacle(&mut commands, Isometry2::new([3.0, 3.0].into(), 0.0));
+}
+
+/// Spawn a single 2x2 obstacle at the given physics position
+pub fn spawn_obstacle(commands: &mut Commands, position: Isometry2<f32>) -> Entity {
     let collider = ColliderBundle {
         shape: ColliderShape::cuboid(1.0, 1.0).into(),
-        position: Isometry2::new([3.0, 3.0].into(), 0.0).into(),
+        position: position.into(),
         ..Default::default()
     };
     commands
         .spawn_bundle(collider)
         .insert(ColliderDebugRender::with_id(2))
-        .insert(ColliderPositionSync::Discrete);
+        .insert(ColliderPositionSync::Discrete)
+        .id()
 }
@@ -4,13 +4,18 @@ use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::na::Isometry2;
 use bevy_rapier2d::prelude::*;
 use pathfinding::prelude::astar;
+use std::cell::Cell;
 use std::f32::consts::TAU;
 use std::ops::Add;
 use std::ops::Sub;
 
+use crate::activity::Dormant;
 use crate::ecs::BondedEntities;
 use crate::ecs::DespawnEvent;
+use crate::hazard::HazardGrid;
 use crate::input::PlayerTag;
+use crate::pathfollowing::{straight_line_clear, within_skip_distance};
+use crate::simple_figure::SimpleFigureTag;
 
 pub struct PathfindingPlugin;
 
@@ -95,6 +100,99 @@ const MAX_TOI: f32 = 1.0; // seconds
 
 const INFLATION_LAYER: f32 = 0.2; // m
 
+/// How many times more it costs to walk through a hazard than around it
+const HAZARD_COST: i32 = 5;
+
+/// Cost of a step into a cell with the given damage per second
+fn hazard_cost(cost: i32, dps: f32) -> i32 {
+    if dps > 0.0 {
+        cost * HAZARD_COST
+    } else {
+        cost
+    }
+}
+
+/// How far in grid cells to look for a free cell around a blocked goal
+const GOAL_SEARCH_RADIUS: i32 = 20;
+
+/// The free cell closest to the goal, searching outward ring by ring.
+/// Each ring is the square of cells at that many steps from the goal.
+fn nearest_free_cell(goal: GridPoint, is_free: impl Fn(GridPoint) -> bool) -> Option<GridPoint> {
+    if is_free(goal) {
+        return Some(goal);
+    }
+    for radius in 1..=GOAL_SEARCH_RADIUS {
+        let ring = (-radius..=radius).flat_map(|dx| {
+            (-radius..=radius)
+                .filter(move |dy| dx.abs() == radius || dy.abs() == radius)
+                .map(move |dy| goal + GridPoint(dx, dy))
+        });
+        // The square's corners are further away than its edges
+        if let Some(cell) = ring
+            .filter(|cell| is_free(*cell))
+            .min_by_key(|cell| (*cell - goal).squared_norm())
+        {
+            return Some(cell);
+        }
+    }
+    None
+}
+
+/// Plan a path from start to goal. A goal inside a wall is moved to the
+/// nearest free cell, and an unreachable goal is replaced by the closest
+/// reachable cell, so NPCs get as near as they can instead of giving up.
+fn plan_path<I>(
+    start: GridPoint,
+    goal: GridPoint,
+    is_free: impl Fn(GridPoint) -> bool,
+    successors: impl Fn(&GridPoint) -> I,
+) -> Option<Vec<GridPoint>>
+where
+    I: IntoIterator<Item = (GridPoint, i32)>,
+{
+    let goal = match nearest_free_cell(goal, is_free) {
+        Some(free) => {
+            if free != goal {
+                info!("goal is blocked, moved to {:?}", free);
+            }
+            free
+        }
+        None => goal,
+    };
+
+    // The expanded cell closest to the goal, the first one found on ties
+    let closest = Cell::new(start);
+    let path = astar(
+        &start,
+        |position| successors(position),
+        |position| position.distance(goal),
+        |position| {
+            if position.distance(goal) < closest.get().distance(goal) {
+                closest.set(*position);
+            }
+            *position == goal
+        },
+    );
+    if let Some((path, _)) = path {
+        return Some(path);
+    }
+
+    // The goal is cut off, for example behind a wall, so get as close as
+    // possible. The search only has to reach a cell known to be reachable.
+    let closest = closest.get();
+    if closest == start {
+        return None;
+    }
+    info!("goal is unreachable, going to {:?} instead", closest);
+    astar(
+        &start,
+        |position| successors(position),
+        |position| position.distance(closest),
+        |position| *position == closest,
+    )
+    .map(|(path, _)| path)
+}
+
 fn compute_path_to_goal(
     mut commands: Commands,
     player: Query<Entity, With<PlayerTag>>,
@@ -105,81 +203,122 @@ fn compute_path_to_goal(
             &ColliderShapeComponent,
             &GoalPosition,
         ),
-        Or<(Added<GoalPosition>, Changed<GoalPosition>)>,
+        (
+            Or<(Added<GoalPosition>, Changed<GoalPosition>)>,
+            Without<Dormant>,
+        ),
     >,
+    figures: Query<(), With<SimpleFigureTag>>,
     query_pipeline: Res<QueryPipeline>,
     collider_query: QueryPipelineColliderComponentsQuery,
+    hazards: Res<HazardGrid>,
 ) {
     let player_entity = player.iter().next();
 
     for (entity, start_position, shape, GoalPosition { position: goal }) in query.iter() {
+        let start: Vec2 = start_position.position.translation.into();
+        let goal_position: Vec2 = goal.translation.into();
+        if within_skip_distance(start, goal_position)
+            && straight_line_clear(
+                &query_pipeline,
+                &QueryPipelineColliderComponentsSet(&collider_query),
+                &figures,
+                entity,
+                &start_position.position,
+                shape,
+                goal_position - start,
+            )
+        {
+            // The straight-line fallback walks there without a path
+            commands.entity(entity).remove::<Path>();
+            continue;
+        }
+
         let start_grid = GridPoint::from(Vec2::from(start_position.position.translation));
         let goal_grid = GridPoint::from(Vec2::from(goal.translation));
         info!("start_grid: {:?}, goal_grid: {:?}", start_grid, goal_grid);
         let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
 
-        let result = astar(
-            &start_grid,
-            |position| {
-                let query_pipeline = &query_pipeline;
-                let collider_set = &collider_set;
-                (0..THETA_STEPS)
-                    .map(move |theta_step| {
-                        let position = position.clone();
-                        let theta: f32 = theta_step as f32 * (TAU / THETA_STEPS as f32);
-                        let vec_position: Vec2 = position.into();
-                        let direction: Vec2 = Mat2::from_angle(theta) * Vec2::X;
-                        let direction = direction.normalize_or_zero();
-
-                        let inflated_shape = match shape.shape_type() {
-                            ShapeType::Cuboid => {
-                                let cuboid = shape.as_cuboid().unwrap();
-                                ColliderShape::cuboid(
-                                    cuboid.half_extents[0] + INFLATION_LAYER,
-                                    cuboid.half_extents[1] + INFLATION_LAYER,
-                                )
-                            }
-                            _ => ColliderShape::cuboid(INFLATION_LAYER, INFLATION_LAYER),
-                        };
-
-                        let toi = match query_pipeline.cast_shape(
-                            collider_set,
-                            &vec_position.into(),
-                            &direction.into(),
-                            &*inflated_shape,
-                            MAX_TOI,
-                            InteractionGroups::new(0b0100, 0b0100),
-                            Some(&|handle| {
-                                handle != entity.handle()
-                                    && match player_entity {
-                                        Some(player) => handle != player.handle(),
-                                        None => true,
-                                    }
-                            }),
-                        ) {
-                            Some((_, toi)) => toi.toi,
-                            None => MAX_TOI,
-                        };
-                        let next = position + GridPoint::from(toi * direction);
-                        let min_x = std::cmp::min(position.0, next.0);
-                        let max_x = std::cmp::max(position.0, next.0);
-                        let min_y = std::cmp::min(position.1, next.1);
-                        let max_y = std::cmp::max(position.1, next.1);
-                        Iterator::zip(min_x..=max_x, min_y..=max_y).map(move |(x, y)| {
-                            let p = GridPoint(x, y);
-                            (p, position.distance(p))
-                        })
+        let filter = |handle: ColliderHandle| {
+            handle != entity.handle()
+                && match player_entity {
+                    Some(player) => handle != player.handle(),
+                    None => true,
+                }
+        };
+        let inflated_shape = match shape.shape_type() {
+            ShapeType::Cuboid => {
+                let cuboid = shape.as_cuboid().unwrap();
+                ColliderShape::cuboid(
+                    cuboid.half_extents[0] + INFLATION_LAYER,
+                    cuboid.half_extents[1] + INFLATION_LAYER,
+                )
+            }
+            _ => ColliderShape::cuboid(INFLATION_LAYER, INFLATION_LAYER),
+        };
+
+        let is_free = |cell: GridPoint| {
+            let cell_position: Vec2 = cell.into();
+            query_pipeline
+                .intersection_with_shape(
+                    &collider_set,
+                    &cell_position.into(),
+                    &*inflated_shape,
+                    InteractionGroups::new(0b0100, 0b0100),
+                    Some(&filter),
+                )
+                .is_none()
+        };
+
+        let successors = |position: &GridPoint| {
+            let query_pipeline = &query_pipeline;
+            let collider_set = &collider_set;
+            let hazards = &hazards;
+            let inflated_shape = &inflated_shape;
+            let filter = &filter;
+            (0..THETA_STEPS)
+                .map(move |theta_step| {
+                    let position = position.clone();
+                    let theta: f32 = theta_step as f32 * (TAU / THETA_STEPS as f32);
+                    let vec_position: Vec2 = position.into();
+                    let direction: Vec2 = Mat2::from_angle(theta) * Vec2::X;
+                    let direction = direction.normalize_or_zero();
+
+                    let toi = match query_pipeline.cast_shape(
+                        collider_set,
+                        &vec_position.into(),
+                        &direction.into(),
+                        &*inflated_shape,
+                        MAX_TOI,
+                        InteractionGroups::new(0b0100, 0b0100),
+                        Some(filter),
+                    ) {
+                        Some((_, toi)) => toi.toi,
+                        None => MAX_TOI,
+                    };
+                    let next = position + GridPoint::from(toi * direction);
+                    let min_x = std::cmp::min(position.0, next.0);
+                    let max_x = std::cmp::max(position.0, next.0);
+                    let min_y = std::cmp::min(position.1, next.1);
+                    let max_y = std::cmp::max(position.1, next.1);
+                    // Hazards are avoided where possible, but not blocked
+                    Iterator::zip(min_x..=max_x, min_y..=max_y).map(move |(x, y)| {
+                        let p = GridPoint(x, y);
+                        (
+                            p,
+                            hazard_cost(position.distance(p), hazards.dps_at(p.into())),
+                        )
                     })
-                    .flatten()
-                    .filter(|(next, _)| *next != *position)
-                    .collect::<Vec<(GridPoint, i32)>>()
-                    .into_iter()
-            },
-            |position| position.distance(goal_grid),
-            |position| *position == goal_grid,
-        );
+                })
+                .flatten()
+                .filter(|(next, _)| *next != *position)
+                .collect::<Vec<(GridPoint, i32)>>()
+                .into_iter()
+        };
 
-        if let Some((path, _)) = result {
+        let result = plan_path(start_grid, goal_grid, is_free, successors);
+
+        if let Some(path) = result {
             commands.entity(entity).insert(Path {
                 points: path.iter().map(|&point| point.into()).collect(),
             });
@@ -231,3 +370,172 @@ fn draw_paths(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::HashSet;
+
+    /// Eight-connected moves on a bounded grid, blocked by walls
+    fn grid_successors(
+        walls: &HashSet<GridPoint>,
+    ) -> impl Fn(&GridPoint) -> Vec<(GridPoint, i32)> + '_ {
+        move |position| {
+            let mut next = Vec::new();
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    let cell = *position + GridPoint(dx, dy);
+                    let in_bounds = cell.0.abs() <= 20 && cell.1.abs() <= 20;
+                    if (dx, dy) != (0, 0) && in_bounds && !walls.contains(&cell) {
+                        let cost = if dx != 0 && dy != 0 { 14 } else { 10 };
+                        next.push((cell, cost));
+                    }
+                }
+            }
+            next
+        }
+    }
+
+    /// A solid square of wall cells
+    fn block(center: GridPoint, radius: i32) -> HashSet<GridPoint> {
+        (-radius..=radius)
+            .flat_map(|dx| (-radius..=radius).map(move |dy| center + GridPoint(dx, dy)))
+            .collect()
+    }
+
+    #[test]
+    fn path_detours_around_hazards() {
+        // A hazard strip of a square meter, between x = 0 and 1 m
+        let mut hazards = HazardGrid {
+            cell_size: Vec2::ONE,
+            ..Default::default()
+        };
+        hazards.add_hazard((0, 0), 2.0);
+        let walls = HashSet::default();
+        let grid = grid_successors(&walls);
+        let successors = |position: &GridPoint| {
+            grid(position)
+                .into_iter()
+                .map(|(next, cost)| (next, hazard_cost(cost, hazards.dps_at(next.into()))))
+                .collect::<Vec<_>>()
+        };
+
+        // Straight through would cross the strip
+        let start = GridPoint(-10, 5);
+        let goal = GridPoint(15, 5);
+        assert!(hazards.dps_at(Vec2::new(0.5, 0.5)) > 0.0);
+        let path = plan_path(start, goal, |_| true, successors).unwrap();
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path
+            .iter()
+            .all(|&point| hazards.dps_at(point.into()) == 0.0));
+    }
+
+    #[test]
+    fn free_goal_is_kept() {
+        let walls = block(GridPoint(0, 0), 1);
+        let goal = GridPoint(5, 5);
+        assert_eq!(
+            nearest_free_cell(goal, |cell| !walls.contains(&cell)),
+            Some(goal)
+        );
+    }
+
+    #[test]
+    fn goal_in_wall_moves_to_nearest_free_cell() {
+        let walls = block(GridPoint(0, 0), 1);
+        // Right at the edge of the block, so one step right is the only
+        // free cell at distance 1
+        let goal = GridPoint(1, 0);
+        assert_eq!(
+            nearest_free_cell(goal, |cell| !walls.contains(&cell)),
+            Some(GridPoint(2, 0))
+        );
+    }
+
+    #[test]
+    fn goal_with_no_free_cell_nearby_is_not_moved() {
+        let goal = GridPoint(0, 0);
+        assert_eq!(nearest_free_cell(goal, |_| false), None);
+    }
+
+    #[test]
+    fn path_to_goal_in_wall_ends_next_to_it() {
+        let walls = block(GridPoint(0, 0), 1);
+        let path = plan_path(
+            GridPoint(-5, 0),
+            GridPoint(1, 0),
+            |cell| !walls.contains(&cell),
+            grid_successors(&walls),
+        )
+        .unwrap();
+        assert_eq!(path.first(), Some(&GridPoint(-5, 0)));
+        assert_eq!(path.last(), Some(&GridPoint(2, 0)));
+        assert!(path.iter().all(|cell| !walls.contains(cell)));
+    }
+
+    #[test]
+    fn path_to_walled_in_goal_ends_at_closest_reachable_cell() {
+        // The goal itself is free, but every cell around it is wall
+        let goal = GridPoint(10, 0);
+        let mut walls = block(goal, 1);
+        walls.remove(&goal);
+        let path = plan_path(
+            GridPoint(-5, 0),
+            goal,
+            |cell| !walls.contains(&cell),
+            grid_successors(&walls),
+        )
+        .unwrap();
+        assert_eq!(path.last(), Some(&GridPoint(8, 0)));
+    }
+
+    #[test]
+    fn unreachable_goal_does_not_explore_the_grid_twice() {
+        let goal = GridPoint(10, 0);
+        let mut walls = block(goal, 1);
+        walls.remove(&goal);
+        let grid = grid_successors(&walls);
+        let expanded = Cell::new(0);
+        let successors = |position: &GridPoint| {
+            expanded.set(expanded.get() + 1);
+            grid(position)
+        };
+        let path = plan_path(
+            GridPoint(-5, 0),
+            goal,
+            |cell| !walls.contains(&cell),
+            successors,
+        )
+        .unwrap();
+        assert_eq!(path.last(), Some(&GridPoint(8, 0)));
+
+        // Every free cell of the 41 by 41 grid once, plus a search that stops
+        // at the closest cell. Flooding the grid again would double it.
+        let reachable = 41 * 41 - walls.len() - 1;
+        assert!(
+            expanded.get() < reachable + reachable / 2,
+            "{} expansions for {} reachable cells",
+            expanded.get(),
+            reachable
+        );
+    }
+
+    #[test]
+    fn no_path_when_nothing_is_closer_than_the_start() {
+        let goal = GridPoint(10, 0);
+        let mut walls = block(goal, 1);
+        walls.remove(&goal);
+        // Boxed in at the start as well
+        let start = GridPoint(-5, 0);
+        walls.extend(block(start, 1));
+        walls.remove(&start);
+        let path = plan_path(
+            start,
+            goal,
+            |cell| !walls.contains(&cell),
+            grid_successors(&walls),
+        );
+        assert_eq!(path, None);
+    }
+}
@@ -1,15 +1,31 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+use std::time::Duration;
+
+use crate::activity::Dormant;
+use crate::ai::{Allegiance, Difficulty, DifficultyCurve};
 use crate::input::MoveAction;
-use crate::pathfinding::Path;
+use crate::pathfinding::{GoalPosition, Path};
+use crate::simple_figure::SimpleFigureTag;
 
 pub struct PathfollowingPlugin;
 
 impl Plugin for PathfollowingPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(reset_carrot)
-            .add_system(go_to_carrot)
+            .add_system(go_to_carrot.label("go_to_carrot").before("movement"))
+            .add_system(
+                blend_handoff
+                    .label("blend_handoff")
+                    .after("go_to_carrot")
+                    .before("movement"),
+            )
+            .add_system(
+                straight_line_fallback
+                    .after("blend_handoff")
+                    .before("movement"),
+            )
             .add_system(goal_checker);
     }
 }
@@ -25,27 +41,122 @@ impl Default for Carrot {
     }
 }
 
-fn reset_carrot(mut commands: Commands, q: Query<Entity, Or<(Added<Path>, Changed<Path>)>>) {
-    for entity in q.iter() {
+/// Eases from the velocity an NPC had before a new path arrived, usually
+/// from the straight-line fallback, into following the path
+#[derive(Component)]
+pub struct PathHandoff {
+    from: Vec2,
+    timer: Timer,
+}
+
+const HANDOFF_DURATION: f32 = 0.1; // seconds
+
+fn reset_carrot(
+    mut commands: Commands,
+    q: Query<(Entity, Option<&MoveAction>), Or<(Added<Path>, Changed<Path>)>>,
+) {
+    for (entity, move_action) in q.iter() {
         info!("Inserting or resetting carrot");
-        commands.entity(entity).insert(Carrot::default());
+        let mut entity_commands = commands.entity(entity);
+        entity_commands
+            .insert(Carrot::default())
+            .remove::<PlannerRetry>();
+        match move_action {
+            Some(move_action) if move_action.desired_velocity != Vec2::ZERO => {
+                entity_commands.insert(PathHandoff {
+                    from: move_action.desired_velocity,
+                    timer: Timer::from_seconds(HANDOFF_DURATION, false),
+                });
+            }
+            _ => (),
+        }
     }
 }
 
 const VELOCITY_SCALE: f32 = 0.5;
 
+/// Speed of an NPC following a path, as a fraction of its move speed
+fn velocity_scale(
+    allegiance: Option<&Allegiance>,
+    difficulty: &Difficulty,
+    curve: &DifficultyCurve,
+) -> f32 {
+    match allegiance {
+        Some(Allegiance::Hostile) => {
+            VELOCITY_SCALE * difficulty.tuning().speed_mult * curve.factor()
+        }
+        _ => VELOCITY_SCALE,
+    }
+}
+
 fn go_to_carrot(
+    curve: Res<DifficultyCurve>,
+    difficulty: Res<Difficulty>,
     mut q: Query<
-        (&mut MoveAction, &RigidBodyPositionComponent, &Carrot, &Path),
+        (
+            &mut MoveAction,
+            &RigidBodyPositionComponent,
+            &Carrot,
+            &Path,
+            Option<&Allegiance>,
+        ),
         Or<(Added<Carrot>, Changed<Carrot>)>,
     >,
 ) {
-    for (mut move_action, pos, carrot, path) in q.iter_mut() {
-        if let Some(&carrot_position) = path.points.get(carrot.index) {
-            let current_position: Vec2 = pos.position.translation.into();
+    for (mut move_action, pos, carrot, path, allegiance) in q.iter_mut() {
+        let velocity_scale = velocity_scale(allegiance, &difficulty, &curve);
+        if let Some(velocity) = carrot_velocity(pos, carrot, path, velocity_scale) {
+            move_action.desired_velocity = velocity;
+        }
+    }
+}
+
+/// Velocity toward the current carrot, if the path has one left
+fn carrot_velocity(
+    pos: &RigidBodyPositionComponent,
+    carrot: &Carrot,
+    path: &Path,
+    velocity_scale: f32,
+) -> Option<Vec2> {
+    let carrot_position = path.points.get(carrot.index)?;
+    let current_position: Vec2 = pos.position.translation.into();
+    Some(velocity_scale * (*carrot_position - current_position).normalize_or_zero())
+}
+
+/// Velocity partway through a handoff, from the old velocity at 0 to the
+/// path's velocity at 1
+fn handoff_velocity(from: Vec2, to: Vec2, progress: f32) -> Vec2 {
+    from.lerp(to, progress.clamp(0.0, 1.0))
+}
 
-            let delta = (carrot_position - current_position).normalize_or_zero();
-            move_action.desired_velocity = (VELOCITY_SCALE * delta).into();
+fn blend_handoff(
+    mut commands: Commands,
+    time: Res<Time>,
+    curve: Res<DifficultyCurve>,
+    difficulty: Res<Difficulty>,
+    mut q: Query<(
+        Entity,
+        &mut MoveAction,
+        &mut PathHandoff,
+        &RigidBodyPositionComponent,
+        &Carrot,
+        &Path,
+        Option<&Allegiance>,
+    )>,
+) {
+    for (entity, mut move_action, mut handoff, pos, carrot, path, allegiance) in q.iter_mut() {
+        let velocity_scale = velocity_scale(allegiance, &difficulty, &curve);
+        let to = match carrot_velocity(pos, carrot, path, velocity_scale) {
+            Some(to) => to,
+            None => {
+                commands.entity(entity).remove::<PathHandoff>();
+                continue;
+            }
+        };
+        handoff.timer.tick(time.delta());
+        move_action.desired_velocity = handoff_velocity(handoff.from, to, handoff.timer.percent());
+        if handoff.timer.finished() {
+            commands.entity(entity).remove::<PathHandoff>();
         }
     }
 }
@@ -77,3 +188,205 @@ fn goal_checker(
         }
     }
 }
+
+/// Retries the planner for an NPC that has a goal but no path, waiting twice
+/// as long after every attempt
+#[derive(Component)]
+pub struct PlannerRetry {
+    timer: Timer,
+}
+
+const PLANNER_RETRY_MIN: f32 = 0.5; // seconds
+const PLANNER_RETRY_MAX: f32 = 8.0; // seconds
+
+/// Goals closer than this are walked to directly when nothing is in the way,
+/// without running the planner at all
+pub const PLANNER_SKIP_DISTANCE: f32 = 1.5; // m
+
+/// Whether a goal is close enough to skip the planner, provided the straight
+/// line to it is clear
+pub fn within_skip_distance(start: Vec2, goal: Vec2) -> bool {
+    start.distance_squared(goal) < PLANNER_SKIP_DISTANCE * PLANNER_SKIP_DISTANCE
+}
+
+/// Whether a character's shape can move along delta without hitting a wall.
+/// Other characters move out of the way, and balls and pickups aren't in the
+/// wall group, so only walls and obstacles block the line.
+pub fn straight_line_clear(
+    query_pipeline: &QueryPipeline,
+    collider_set: &QueryPipelineColliderComponentsSet,
+    figures: &Query<(), With<SimpleFigureTag>>,
+    entity: Entity,
+    position: &Isometry<Real>,
+    shape: &ColliderShapeComponent,
+    delta: Vec2,
+) -> bool {
+    query_pipeline
+        .cast_shape(
+            collider_set,
+            position,
+            &delta.into(),
+            &***shape,
+            1.0,
+            InteractionGroups::new(0b0100, 0b0100),
+            Some(&|handle| handle.entity() != entity && figures.get(handle.entity()).is_err()),
+        )
+        .is_none()
+}
+
+/// What an NPC without a path does: walk straight at the goal when the line
+/// is clear, otherwise hold still
+fn fallback_velocity(delta: Vec2, line_clear: bool, velocity_scale: f32) -> Vec2 {
+    if line_clear {
+        delta.normalize_or_zero() * velocity_scale
+    } else {
+        Vec2::ZERO
+    }
+}
+
+/// Without a path, e.g. before the planner has run, after it failed, or for a
+/// goal too close to plan for, walk straight at the goal if nothing is in the
+/// way. Otherwise stand still facing the goal until a retried plan comes
+/// through.
+fn straight_line_fallback(
+    mut commands: Commands,
+    time: Res<Time>,
+    curve: Res<DifficultyCurve>,
+    difficulty: Res<Difficulty>,
+    query_pipeline: Res<QueryPipeline>,
+    collider_query: QueryPipelineColliderComponentsQuery,
+    figures: Query<(), With<SimpleFigureTag>>,
+    mut q: Query<
+        (
+            Entity,
+            &mut GoalPosition,
+            &RigidBodyPositionComponent,
+            &ColliderShapeComponent,
+            &mut MoveAction,
+            Option<&Allegiance>,
+            Option<&mut TextureAtlasSprite>,
+            Option<&mut PlannerRetry>,
+        ),
+        (Without<Path>, Without<Dormant>),
+    >,
+) {
+    let collider_set = QueryPipelineColliderComponentsSet(&collider_query);
+
+    for (entity, mut goal, pos, shape, mut move_action, allegiance, sprite, retry) in q.iter_mut() {
+        let current_position: Vec2 = pos.position.translation.into();
+        let goal_position: Vec2 = goal.position.translation.into();
+        let delta = goal_position - current_position;
+        if delta.length_squared() < GOAL_TOLERANCE {
+            move_action.desired_velocity = Vec2::ZERO;
+            continue;
+        }
+
+        let line_clear = straight_line_clear(
+            &query_pipeline,
+            &collider_set,
+            &figures,
+            entity,
+            &pos.position,
+            shape,
+            delta,
+        );
+        move_action.desired_velocity = fallback_velocity(
+            delta,
+            line_clear,
+            velocity_scale(allegiance, &difficulty, &curve),
+        );
+        if !line_clear {
+            if let Some(mut sprite) = sprite {
+                sprite.flip_x = delta.x < 0.0;
+            }
+        }
+
+        // Close goals in the open never need the planner
+        if line_clear && within_skip_distance(current_position, goal_position) {
+            continue;
+        }
+
+        match retry {
+            Some(mut retry) => {
+                if retry.timer.tick(time.delta()).just_finished() {
+                    // Touching the goal makes the planner run again
+                    goal.set_changed();
+                    let backoff =
+                        (retry.timer.duration().as_secs_f32() * 2.0).min(PLANNER_RETRY_MAX);
+                    retry.timer = Timer::new(Duration::from_secs_f32(backoff), false);
+                }
+            }
+            None => {
+                commands.entity(entity).insert(PlannerRetry {
+                    timer: Timer::from_seconds(PLANNER_RETRY_MIN, false),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostile_speed_follows_the_difficulty() {
+        let curve = DifficultyCurve::default();
+        let hostile = Some(&Allegiance::Hostile);
+        let friendly = Some(&Allegiance::Friendly);
+        assert_eq!(
+            velocity_scale(hostile, &Difficulty::Normal, &curve),
+            VELOCITY_SCALE
+        );
+        let hard = velocity_scale(hostile, &Difficulty::Hard, &curve);
+        assert!((hard - VELOCITY_SCALE * 1.3).abs() < 1e-6);
+        let easy = velocity_scale(hostile, &Difficulty::Easy, &curve);
+        assert!((easy - VELOCITY_SCALE * 0.8).abs() < 1e-6);
+        // Friendlies and the player keep their speed
+        assert_eq!(
+            velocity_scale(friendly, &Difficulty::Hard, &curve),
+            VELOCITY_SCALE
+        );
+        assert_eq!(
+            velocity_scale(None, &Difficulty::Hard, &curve),
+            VELOCITY_SCALE
+        );
+    }
+
+    #[test]
+    fn close_goals_skip_the_planner() {
+        let start = Vec2::new(2.0, 3.0);
+        assert!(within_skip_distance(start, start + Vec2::new(1.0, 0.0)));
+        assert!(within_skip_distance(start, start + Vec2::new(0.0, -1.4)));
+        assert!(!within_skip_distance(start, start + Vec2::new(1.5, 0.0)));
+        assert!(!within_skip_distance(start, start + Vec2::new(5.0, 5.0)));
+    }
+
+    #[test]
+    fn blocked_line_holds_position() {
+        let delta = Vec2::new(3.0, 4.0);
+        assert_eq!(fallback_velocity(delta, false, 0.5), Vec2::ZERO);
+    }
+
+    #[test]
+    fn clear_line_walks_at_the_goal() {
+        let delta = Vec2::new(3.0, 4.0);
+        let velocity = fallback_velocity(delta, true, 0.5);
+        assert!((velocity - Vec2::new(0.3, 0.4)).length() < 1e-6);
+    }
+
+    #[test]
+    fn handoff_blends_without_a_jump() {
+        let from = Vec2::new(0.5, 0.0);
+        let to = Vec2::new(0.0, 0.5);
+        assert_eq!(handoff_velocity(from, to, 0.0), from);
+        assert_eq!(handoff_velocity(from, to, 1.0), to);
+        let halfway = handoff_velocity(from, to, 0.5);
+        assert!((halfway - Vec2::new(0.25, 0.25)).length() < 1e-6);
+        // Each step of the blend moves only part of the way
+        let step = handoff_velocity(from, to, 0.25) - from;
+        assert!(step.length() < (to - from).length() / 2.0);
+        // Timers can overshoot, which must not overshoot the velocity
+        assert_eq!(handoff_velocity(from, to, 1.5), to);
+    }
+}
@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::Rng;
+
+use crate::ecs::DespawnEvent;
+use crate::health::{DeathEvent, Health};
+use crate::input::PlayerTag;
+use crate::simple_figure::SimpleFigureTag;
+
+pub struct PickupPlugin;
+
+/// Only characters pick things up. Staying out of the wall group keeps
+/// pickups from blocking sight lines and paths.
+pub const PICKUP_GROUPS: InteractionGroups = InteractionGroups::new(0b0001, 0b0001);
+
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DropTable>()
+            .add_system(drop_pickups.after("health_despawner"))
+            .add_system(collect_pickups);
+    }
+}
+
+/// What NPCs drop when they die
+pub struct DropTable {
+    /// Chance from 0 to 1 that an NPC drops a health pickup
+    pub health_pickup_chance: f32,
+    pub health_pickup_heal: i32,
+}
+
+impl Default for DropTable {
+    fn default() -> Self {
+        DropTable {
+            health_pickup_chance: 0.3,
+            health_pickup_heal: 2,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct HealthPickup {
+    pub heal: i32,
+}
+
+const PICKUP_RADIUS: f32 = 0.2; // m
+
+fn drop_pickups(
+    mut commands: Commands,
+    drop_table: Res<DropTable>,
+    rc: Res<RapierConfiguration>,
+    npcs: Query<&RigidBodyPositionComponent, (With<SimpleFigureTag>, Without<PlayerTag>)>,
+    mut deaths: EventReader<DeathEvent>,
+) {
+    let mut rng = rand::thread_rng();
+    for DeathEvent(entity) in deaths.iter() {
+        if let Ok(position) = npcs.get(*entity) {
+            if rng.gen::<f32>() >= drop_table.health_pickup_chance {
+                continue;
+            }
+            info!("Dropping health pickup");
+            let shape = shapes::Circle {
+                radius: PICKUP_RADIUS * rc.scale,
+                center: Vec2::ZERO,
+            };
+            commands
+                .spawn_bundle(GeometryBuilder::build_as(
+                    &shape,
+                    DrawMode::Fill(FillMode::color(Color::GREEN)),
+                    Transform::from_translation(Vec3::new(0.0, 0.0, 1.0)),
+                ))
+                .insert_bundle(ColliderBundle {
+                    collider_type: ColliderType::Sensor.into(),
+                    shape: ColliderShape::ball(PICKUP_RADIUS).into(),
+                    position: position.position.into(),
+                    flags: ColliderFlags {
+                        collision_groups: PICKUP_GROUPS,
+                        active_events: ActiveEvents::INTERSECTION_EVENTS,
+                        ..Default::default()
+                    }
+                    .into(),
+                    ..Default::default()
+                })
+                .insert(ColliderPositionSync::Discrete)
+                .insert(HealthPickup {
+                    heal: drop_table.health_pickup_heal,
+                });
+        }
+    }
+}
+
+fn collect_pickups(
+    pickups: Query<&HealthPickup>,
+    mut players: Query<&mut Health, With<PlayerTag>>,
+    mut intersection_events: EventReader<IntersectionEvent>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    for intersection_event in intersection_events.iter() {
+        if !intersection_event.intersecting {
+            continue;
+        }
+        let c1 = intersection_event.collider1.entity();
+        let c2 = intersection_event.collider2.entity();
+        for (pickup_entity, collector) in [(c1, c2), (c2, c1)] {
+            if let Ok(HealthPickup { heal }) = pickups.get(pickup_entity) {
+                if let Ok(mut health) = players.get_mut(collector) {
+                    health.current = std::cmp::min(health.current + heal, health.max);
+                    info!("Picked up health: {}/{}", health.current, health.max);
+                    despawn.send(DespawnEvent(pickup_entity));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::ball::BallTag;
+use crate::ecs::DespawnEvent;
+use crate::input::PlayerTag;
+use crate::simple_figure::SimpleFigureTag;
+
+/// Catches bodies the solver has blown up, with NaN or absurd positions or
+/// velocities, and puts them back where they last were sane before anything
+/// downstream reads the garbage
+pub struct SanityPlugin;
+
+impl Plugin for SanityPlugin {
+    fn build(&self, app: &mut App) {
+        // Sweep right after the physics step, before its results are written
+        // to Transforms, so nothing downstream ever sees them
+        app.init_resource::<PhysicsIncidents>()
+            .add_system_to_stage(
+                PhysicsStages::SyncTransforms,
+                track_valid_positions.before(PhysicsSystems::SyncTransforms),
+            )
+            .add_system(add_last_valid_position);
+    }
+}
+
+/// Anything further than this from the origin has flown out of the world
+const MAX_COORDINATE: f32 = 10_000.0; // m
+
+/// Faster than anything in the sandbox moves on purpose
+const MAX_SPEED: f32 = 1_000.0; // m/s
+
+/// How many bodies had to be reset, for the debug tools
+#[derive(Default)]
+pub struct PhysicsIncidents(pub usize);
+
+/// Where the body was the last time its position was sane
+#[derive(Component)]
+pub struct LastValidTransform(pub Isometry<Real>);
+
+fn position_is_sane(position: &Isometry<Real>) -> bool {
+    let translation: Vec2 = position.translation.into();
+    translation.is_finite()
+        && translation.abs().max_element() <= MAX_COORDINATE
+        && position.rotation.angle().is_finite()
+}
+
+fn velocity_is_sane(velocity: &RigidBodyVelocity) -> bool {
+    let linvel = Vec2::new(velocity.linvel.x, velocity.linvel.y);
+    linvel.is_finite() && linvel.length() <= MAX_SPEED && velocity.angvel.is_finite()
+}
+
+fn add_last_valid_position(
+    mut commands: Commands,
+    bodies: Query<
+        (Entity, &RigidBodyPositionComponent),
+        (
+            Or<(With<SimpleFigureTag>, With<BallTag>)>,
+            Without<LastValidTransform>,
+        ),
+    >,
+) {
+    // Characters and balls are the only bodies the solver moves, so only
+    // they can explode
+    for (entity, position) in bodies.iter() {
+        if position_is_sane(&position.position) {
+            commands
+                .entity(entity)
+                .insert(LastValidTransform(position.position));
+        }
+    }
+}
+
+fn track_valid_positions(
+    mut incidents: ResMut<PhysicsIncidents>,
+    mut bodies: Query<(
+        Entity,
+        &mut RigidBodyPositionComponent,
+        &mut RigidBodyVelocityComponent,
+        &mut LastValidTransform,
+        Option<&PlayerTag>,
+        Option<&SimpleFigureTag>,
+        Option<&BallTag>,
+    )>,
+    mut despawn: EventWriter<DespawnEvent>,
+) {
+    for (entity, mut position, mut velocity, mut last_valid, player, figure, ball) in
+        bodies.iter_mut()
+    {
+        let position_sane = position_is_sane(&position.position);
+        let velocity_sane = velocity_is_sane(&velocity);
+        if position_sane && velocity_sane {
+            last_valid.0 = position.position;
+            continue;
+        }
+
+        incidents.0 += 1;
+        let kind = match (player, figure, ball) {
+            (Some(_), _, _) => "player",
+            (None, Some(_), _) => "character",
+            (None, None, Some(_)) => "ball",
+            _ => "body",
+        };
+        warn!(
+            "Resetting {} {:?}: position {:?}, linvel {:?}, angvel {}",
+            kind, entity, position.position.translation, velocity.linvel, velocity.angvel
+        );
+
+        // Balls are cheap and short-lived, so drop them once they are back
+        // in a sane place
+        if ball.is_some() {
+            despawn.send(DespawnEvent(entity));
+        }
+        if !position_sane {
+            position.position = last_valid.0;
+            position.next_position = last_valid.0;
+        }
+        velocity.linvel = Vector::zeros();
+        velocity.angvel = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanity_app() -> App {
+        let mut app = App::new();
+        app.add_event::<DespawnEvent>()
+            .init_resource::<PhysicsIncidents>()
+            .add_system(track_valid_positions)
+            .add_system_to_stage(CoreStage::PostUpdate, add_last_valid_position);
+        app
+    }
+
+    fn spawn_figure(app: &mut App, x: f32, y: f32) -> Entity {
+        let position: RigidBodyPositionComponent = Isometry::translation(x, y).into();
+        let velocity: RigidBodyVelocityComponent = RigidBodyVelocity::default().into();
+        app.world
+            .spawn()
+            .insert(SimpleFigureTag)
+            .insert(position)
+            .insert(velocity)
+            .id()
+    }
+
+    fn translation(app: &App, entity: Entity) -> Vec2 {
+        app.world
+            .get::<RigidBodyPositionComponent>(entity)
+            .unwrap()
+            .position
+            .translation
+            .into()
+    }
+
+    #[test]
+    fn nan_position_is_restored() {
+        let mut app = sanity_app();
+        let figure = spawn_figure(&mut app, 1.0, 2.0);
+        // Once to start tracking, once more to record a valid position
+        app.update();
+        app.update();
+
+        let mut position = app
+            .world
+            .get_mut::<RigidBodyPositionComponent>(figure)
+            .unwrap();
+        position.position = Isometry::translation(f32::NAN, 2.0);
+        app.update();
+
+        assert_eq!(translation(&app, figure), Vec2::new(1.0, 2.0));
+        assert_eq!(app.world.get_resource::<PhysicsIncidents>().unwrap().0, 1);
+    }
+
+    #[test]
+    fn runaway_velocity_is_stopped() {
+        let mut app = sanity_app();
+        let figure = spawn_figure(&mut app, 1.0, 2.0);
+        app.update();
+
+        let mut velocity = app
+            .world
+            .get_mut::<RigidBodyVelocityComponent>(figure)
+            .unwrap();
+        velocity.linvel = Vector::new(1e9, 0.0);
+        app.update();
+
+        let velocity = app.world.get::<RigidBodyVelocityComponent>(figure).unwrap();
+        assert_eq!(velocity.linvel, Vector::zeros());
+        assert_eq!(translation(&app, figure), Vec2::new(1.0, 2.0));
+        assert_eq!(app.world.get_resource::<PhysicsIncidents>().unwrap().0, 1);
+    }
+
+    #[test]
+    fn sane_bodies_are_left_alone() {
+        let mut app = sanity_app();
+        let figure = spawn_figure(&mut app, 1.0, 2.0);
+        app.update();
+        app.update();
+        assert_eq!(translation(&app, figure), Vec2::new(1.0, 2.0));
+        assert_eq!(app.world.get_resource::<PhysicsIncidents>().unwrap().0, 0);
+    }
+}
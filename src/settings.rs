@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::Difficulty;
+use crate::aim::{AimAssist, TrajectoryPreview};
+use crate::camera::CameraLead;
+use crate::effects::DustSettings;
+use crate::input::KeyboardShoot;
+use crate::MSAA_SAMPLES;
+
+/// Loads user settings at startup and saves them whenever they change.
+///
+/// Must be added before the plugins owning the resources it configures, so
+/// their init_resource doesn't overwrite the loaded values.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = Settings::load();
+        app.insert_resource(settings.difficulty)
+            .insert_resource(AimAssist {
+                enabled: settings.aim_assist,
+                ..Default::default()
+            })
+            .insert_resource(TrajectoryPreview {
+                always: settings.always_show_trajectory,
+                ..Default::default()
+            })
+            .insert_resource(DustSettings {
+                enabled: settings.dust,
+                ..Default::default()
+            })
+            .insert_resource(CameraLead {
+                factor: settings.camera_lead,
+                ..Default::default()
+            })
+            .insert_resource(KeyboardShoot {
+                enabled: settings.keyboard_shoot,
+                ..Default::default()
+            })
+            .insert_resource(Msaa {
+                samples: settings.msaa_samples,
+            })
+            .insert_resource(settings)
+            .add_system_to_stage(CoreStage::Last, save_settings);
+    }
+}
+
+/// Bumped whenever the meaning of an existing field changes. New fields don't
+/// need a bump, since missing fields are filled in with defaults.
+const SETTINGS_VERSION: u32 = 1;
+
+/// Everything the user can configure, persisted in settings.ron
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub difficulty: Difficulty,
+    pub aim_assist: bool,
+    pub always_show_trajectory: bool,
+    pub dust: bool,
+    pub camera_lead: f32,
+    pub keyboard_shoot: bool,
+    /// Anti-aliasing samples per pixel, 1 (off) or 4
+    pub msaa_samples: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            difficulty: Difficulty::default(),
+            aim_assist: AimAssist::default().enabled,
+            always_show_trajectory: TrajectoryPreview::default().always,
+            dust: DustSettings::default().enabled,
+            camera_lead: CameraLead::default().factor,
+            keyboard_shoot: KeyboardShoot::default().enabled,
+            msaa_samples: Msaa::default().samples,
+        }
+    }
+}
+
+/// Only the version, to decide how to read the rest of the file
+#[derive(Deserialize)]
+struct SettingsVersion {
+    version: u32,
+}
+
+impl Settings {
+    /// settings.ron in the platform's config directory
+    pub fn path() -> Option<PathBuf> {
+        Some(
+            dirs::config_dir()?
+                .join("bevy_sandbox")
+                .join("settings.ron"),
+        )
+    }
+
+    /// Load the settings file, falling back to defaults if there is none or
+    /// it can't be read
+    pub fn load() -> Self {
+        let path = match Settings::path() {
+            Some(path) => path,
+            None => return Settings::default(),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => {
+                info!("No settings at {}, using defaults", path.display());
+                return Settings::default();
+            }
+        };
+        match Settings::parse(&text) {
+            Ok(settings) => {
+                info!("Loaded settings from {}", path.display());
+                settings
+            }
+            Err(err) => {
+                warn!("Ignoring settings at {}: {}", path.display(), err);
+                Settings::default()
+            }
+        }
+    }
+
+    /// Parse settings of any known version, migrating them forward
+    fn parse(text: &str) -> Result<Self, String> {
+        let SettingsVersion { version } = ron::from_str(text).map_err(|err| err.to_string())?;
+        if version > SETTINGS_VERSION {
+            return Err(format!(
+                "version {} is newer than supported version {}",
+                version, SETTINGS_VERSION
+            ));
+        }
+        // Version 1 is the first, so there is nothing to migrate from yet
+        let mut settings: Settings = ron::from_str(text).map_err(|err| err.to_string())?;
+        settings.version = SETTINGS_VERSION;
+        if !MSAA_SAMPLES.contains(&settings.msaa_samples) {
+            warn!(
+                "Unsupported MSAA sample count {}, using {}",
+                settings.msaa_samples,
+                Msaa::default().samples
+            );
+            settings.msaa_samples = Msaa::default().samples;
+        }
+        if let Err(err) = settings.difficulty.tuning().validate() {
+            warn!(
+                "Invalid difficulty {:?}: {}, using {:?}",
+                settings.difficulty,
+                err,
+                Difficulty::default()
+            );
+            settings.difficulty = Difficulty::default();
+        }
+        Ok(settings)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Settings::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        fs::write(&path, text).map_err(|err| err.to_string())
+    }
+}
+
+/// Copy the configurable resources back into Settings and write them out
+/// whenever one of them changed during play
+fn save_settings(
+    mut settings: ResMut<Settings>,
+    difficulty: Res<Difficulty>,
+    aim_assist: Res<AimAssist>,
+    trajectory_preview: Res<TrajectoryPreview>,
+    dust: Res<DustSettings>,
+    camera_lead: Res<CameraLead>,
+    keyboard_shoot: Res<KeyboardShoot>,
+    msaa: Res<Msaa>,
+) {
+    let current = Settings {
+        version: SETTINGS_VERSION,
+        difficulty: *difficulty,
+        aim_assist: aim_assist.enabled,
+        always_show_trajectory: trajectory_preview.always,
+        dust: dust.enabled,
+        camera_lead: camera_lead.factor,
+        keyboard_shoot: keyboard_shoot.enabled,
+        msaa_samples: msaa.samples,
+    };
+    if current == *settings {
+        return;
+    }
+
+    *settings = current;
+    match settings.save() {
+        Ok(()) => info!("Saved settings"),
+        Err(err) => warn!("Failed to save settings: {}", err),
+    }
+}
@@ -5,9 +5,13 @@ use bevy::prelude::*;
 use bevy_rapier2d::{na::Isometry2, prelude::*};
 use std::f32::consts::FRAC_PI_4;
 
+use crate::ai::{npc_stats, Allegiance, Difficulty};
+use crate::assets::PreloadAssets;
+use crate::ball::Owner;
 use crate::camera::CameraTarget;
 use crate::health::Health;
-use crate::input::{MoveAction, PlayerTag};
+use crate::input::{Facing, MoveAction, MoveSpeed, PlayerTag};
+use crate::tuning::Tuning;
 
 pub struct SimpleFigurePlugin;
 
@@ -58,6 +62,7 @@ impl FromWorld for SimpleFigureTextureAtlasHandle {
     fn from_world(world: &mut World) -> Self {
         let asset_server = world.get_resource::<AssetServer>().unwrap();
         let texture_atlas = get_texture_atlas(asset_server, &SPRITE_SHEET);
+        PreloadAssets::register(world, texture_atlas.texture.clone_untyped());
         let mut texture_atlases = world.get_resource_mut::<Assets<TextureAtlas>>().unwrap();
         let texture_atlas_handle = texture_atlases.add(texture_atlas);
         SimpleFigureTextureAtlasHandle {
@@ -194,6 +199,8 @@ pub struct SimpleFigureSpawnEvent {
     pub scale: f32,
     pub z: f32,
     pub playable: bool,
+    /// Ignored for playable figures, which are always Friendly
+    pub allegiance: Allegiance,
 }
 
 impl Default for SimpleFigureSpawnEvent {
@@ -203,6 +210,7 @@ impl Default for SimpleFigureSpawnEvent {
             scale: 1.0,
             z: 2.0,
             playable: false,
+            allegiance: Allegiance::default(),
         }
     }
 }
@@ -220,35 +228,88 @@ fn spawn(
     mut commands: Commands,
     texture_atlas_handle: Res<SimpleFigureTextureAtlasHandle>,
     animations: Res<SimpleFigureAnimationHandles>,
+    tuning: Res<Tuning>,
+    difficulty: Res<Difficulty>,
     mut spawn_events: EventReader<SimpleFigureSpawnEvent>,
 ) {
     for spawn_event in spawn_events.iter() {
-        let mut entity_commands = commands.spawn_bundle(SimpleFigureBundle {
-            sprite_sheet_bundle: SpriteSheetBundle {
-                texture_atlas: texture_atlas_handle.handle.clone(),
-                transform: Transform::from_scale(Vec3::splat(spawn_event.scale))
-                    * Transform::from_translation(Vec3::new(0.0, 0.0, spawn_event.z)),
+        spawn_simple_figure(
+            &mut commands,
+            &texture_atlas_handle,
+            &animations,
+            &tuning,
+            &difficulty,
+            spawn_event,
+        );
+    }
+}
+
+const FRIENDLY_TINT: Color = Color::rgb(0.6, 1.0, 0.6);
+
+/// Spawn a single simple figure immediately, for callers that need its entity
+pub fn spawn_simple_figure(
+    commands: &mut Commands,
+    texture_atlas_handle: &SimpleFigureTextureAtlasHandle,
+    animations: &SimpleFigureAnimationHandles,
+    tuning: &Tuning,
+    difficulty: &Difficulty,
+    spawn_event: &SimpleFigureSpawnEvent,
+) -> Entity {
+    let allegiance = if spawn_event.playable {
+        Allegiance::Friendly
+    } else {
+        spawn_event.allegiance
+    };
+    // Tint friendly NPCs so they can be told apart from zombies
+    let color = if !spawn_event.playable && allegiance == Allegiance::Friendly {
+        FRIENDLY_TINT
+    } else {
+        Color::WHITE
+    };
+    let mut entity_commands = commands.spawn_bundle(SimpleFigureBundle {
+        sprite_sheet_bundle: SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                color,
                 ..Default::default()
             },
-            animation: animations.front_stationary.clone(),
-            rigid_body_bundle: RigidBodyBundle {
-                mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
-                forces: RigidBodyForces {
-                    gravity_scale: 0.0,
-                    ..Default::default()
-                }
-                .into(),
-                position: spawn_event.position.into(),
+            texture_atlas: texture_atlas_handle.handle.clone(),
+            transform: Transform::from_scale(Vec3::splat(spawn_event.scale))
+                * Transform::from_translation(Vec3::new(0.0, 0.0, spawn_event.z)),
+            ..Default::default()
+        },
+        animation: animations.front_stationary.clone(),
+        rigid_body_bundle: RigidBodyBundle {
+            mass_properties: RigidBodyMassPropsFlags::ROTATION_LOCKED.into(),
+            forces: RigidBodyForces {
+                gravity_scale: 0.0,
                 ..Default::default()
-            },
+            }
+            .into(),
+            position: spawn_event.position.into(),
             ..Default::default()
-        });
-        if spawn_event.playable {
-            entity_commands.insert(PlayerTag).insert(CameraTarget);
-        } else {
-            entity_commands.insert(Health::from_max(5));
+        },
+        ..Default::default()
+    });
+    entity_commands.insert(allegiance);
+    if spawn_event.playable {
+        entity_commands
+            .insert(PlayerTag)
+            .insert(CameraTarget)
+            .insert(Health::from_max(tuning.combat.player_max_health))
+            .insert(MoveSpeed(tuning.movement.player_speed))
+            .insert(Facing::default());
+    } else {
+        let (health, collision_damage) = npc_stats(allegiance, &difficulty.tuning());
+        entity_commands.insert(health);
+        if let Some(collision_damage) = collision_damage {
+            // Owning itself keeps it from hurting other hostiles
+            let entity = entity_commands.id();
+            entity_commands
+                .insert(collision_damage)
+                .insert(Owner(entity));
         }
     }
+    entity_commands.id()
 }
 
 fn animation_control(
@@ -1,44 +1,65 @@
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+use bevy::utils::HashMap;
 use bevy::{prelude::*, render::render_resource::TextureUsages};
 use bevy_ecs_tilemap::prelude::*;
 use bevy_rapier2d::prelude::*;
 use nalgebra::{Isometry2, UnitComplex};
 use std::f32::consts::TAU;
+use std::time::Instant;
 use std::{path::Path, sync::Arc};
 
 use tiled::{Loader, ObjectShape, Tileset};
 
+use crate::ai::Allegiance;
+use crate::assets::{assets_ready, PreloadAssets};
+use crate::ecs::DespawnEvent;
 use crate::simple_figure::SimpleFigureSpawnEvent;
 
 // TODO: change this from a constant so we can handle multiple maps
 const MAP_ID: u16 = 0u16;
 
+/// Layer whose tiles' collision shapes become wall colliders
+const WALL_LAYER_ID: u16 = 1;
+
 pub struct TiledPlugin;
 
 impl Plugin for TiledPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<TilemapSpawnEvent>()
+            .add_event::<MapObjectEvent>()
+            .add_event::<MapReadyEvent>()
             // .add_plugin(RapierRenderPlugin)
             .add_system(spawn)
             .add_system(set_texture_filters_to_nearest)
-            .add_system(process_object_layers)
+            .add_system(process_object_layers.with_run_criteria(assets_ready))
+            .add_system(spawn_simple_figures)
             .add_system(add_colliders);
     }
 }
 
 #[derive(Component)]
-pub struct TiledMapComponent(tiled::Map);
+pub struct TiledMapComponent(pub tiled::Map);
 
 #[derive(Bundle)]
 pub struct TiledMapBundle {
     pub ecs_map: bevy_ecs_tilemap::Map,
     pub tiled_map: TiledMapComponent,
     pub transform: Transform,
+    pub global_transform: GlobalTransform,
 }
 
 pub struct TilemapSpawnEvent {
     pub path: &'static Path,
 }
 
+/// Marks a map whose layers and wall colliders have all been spawned
+#[derive(Component)]
+pub struct MapReady;
+
+/// Sent once a map's layers and wall colliders have all been spawned, so
+/// anything placed on the map can be spawned after its walls exist
+pub struct MapReadyEvent(pub Entity);
+
 pub fn set_texture_filters_to_nearest(
     mut texture_events: EventReader<AssetEvent<Image>>,
     mut textures: ResMut<Assets<Image>>,
@@ -68,6 +89,16 @@ fn load_texture_atlas(tileset: &Tileset, asset_server: &Res<AssetServer>) -> Opt
     None
 }
 
+/// Color to draw a layer's tiles with, from its authored tint and opacity
+fn layer_color(layer: &tiled::Layer) -> Color {
+    let mut color = match layer.tint_color {
+        Some(tint) => Color::rgba_u8(tint.red, tint.green, tint.blue, tint.alpha),
+        None => Color::WHITE,
+    };
+    color.set_a(color.a() * layer.opacity);
+    color
+}
+
 fn process_layer(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
@@ -107,6 +138,7 @@ fn process_layer(
                 }
             };
 
+            let color = layer_color(layer);
             let layer_entity = LayerBuilder::<TileBundle>::new_batch(
                 commands,
                 layer_settings.clone(),
@@ -132,6 +164,7 @@ fn process_layer(
                         flip_x: tile.flip_h,
                         flip_y: tile.flip_v,
                         flip_d: tile.flip_d,
+                        color,
                         ..Default::default()
                     };
 
@@ -156,6 +189,7 @@ fn process_layer(
 fn spawn(
     mut spawn_events: EventReader<TilemapSpawnEvent>,
     asset_server: Res<AssetServer>,
+    mut preload: ResMut<PreloadAssets>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
@@ -169,6 +203,8 @@ fn spawn(
         let tileset = tiled_map.tilesets().first().unwrap();
         // TODO: make this handle multiple textures
         let texture_handle = load_texture_atlas(&tileset, &asset_server).unwrap();
+        // Hold off spawning map objects until the tileset is loaded
+        preload.0.push(texture_handle.clone_untyped());
 
         for layer in tiled_map.layers() {
             process_layer(
@@ -181,62 +217,139 @@ fn spawn(
                 &mut ecs_map,
             );
         }
-        commands.spawn_bundle(TiledMapBundle {
+        // The same entity owns the tile layers and, once they are added, the
+        // walls, so despawning it recursively cleans up the whole map
+        commands.entity(map_entity).insert_bundle(TiledMapBundle {
             ecs_map,
             tiled_map: TiledMapComponent(tiled_map),
             transform: Transform::from_xyz(0.0, 0.0, 0.0),
+            global_transform: GlobalTransform::identity(),
         });
     }
 }
 
+/// An object from a Tiled object layer, with its position converted into
+/// physics coordinates
+#[derive(Clone, Debug)]
+pub struct MapObject {
+    pub name: String,
+    pub obj_type: String,
+    pub shape: ObjectShape,
+    pub position: Isometry2<f32>,
+    pub properties: tiled::Properties,
+}
+
+impl MapObject {
+    pub fn get_bool(&self, key: &str, default: bool) -> bool {
+        match self.properties.get(key) {
+            Some(tiled::PropertyValue::BoolValue(value)) => *value,
+            Some(other) => self.wrong_type(key, other, default),
+            None => default,
+        }
+    }
+
+    pub fn get_f32(&self, key: &str, default: f32) -> f32 {
+        match self.properties.get(key) {
+            Some(tiled::PropertyValue::FloatValue(value)) => *value,
+            Some(tiled::PropertyValue::IntValue(value)) => *value as f32,
+            Some(other) => self.wrong_type(key, other, default),
+            None => default,
+        }
+    }
+
+    pub fn get_string(&self, key: &str, default: &str) -> String {
+        match self.properties.get(key) {
+            Some(tiled::PropertyValue::StringValue(value)) => value.clone(),
+            Some(other) => self.wrong_type(key, other, default.to_string()),
+            None => default.to_string(),
+        }
+    }
+
+    fn wrong_type<T>(&self, key: &str, value: &tiled::PropertyValue, default: T) -> T {
+        warn!(
+            "Property {} of map object {:?} has unexpected value {:?}",
+            key, self.name, value
+        );
+        default
+    }
+}
+
+/// Sent for every object in a map's object layers when the map is loaded.
+/// Map-driven features subscribe to these rather than parsing Tiled data.
+pub struct MapObjectEvent(pub MapObject);
+
+/// Runs once the map is ready rather than as soon as it is spawned, so objects
+/// are never placed before the walls around them exist. Uses Added rather than
+/// MapReadyEvent since it can be held back by assets_ready for many frames.
 fn process_object_layers(
-    tiled_map_query: Query<&TiledMapComponent, Changed<TiledMapComponent>>,
-    mut spawn_event: EventWriter<SimpleFigureSpawnEvent>,
+    tiled_map_query: Query<&TiledMapComponent, Added<MapReady>>,
+    mut map_object_event: EventWriter<MapObjectEvent>,
     rc: Res<RapierConfiguration>,
 ) {
     for TiledMapComponent(tiled_map) in tiled_map_query.iter() {
-        if let Some(object_layer) = tiled_map.layers().find_map(|layer| {
-            return match layer.layer_type() {
-                tiled::LayerType::Objects(object_layer) => Some(object_layer),
-                _ => None,
-            };
-        }) {
-            info!("Found object layer");
-            for object in object_layer.objects() {
-                if object.obj_type.as_str() == "simple_figure" {
-                    let y_pixels = (tiled_map.height * tiled_map.tile_height) as f32 - object.y;
-
-                    if let ObjectShape::Rect {
-                        width: _,
-                        height: _,
-                    } = object.shape
-                    {
-                        let playable = match object
-                            .properties
-                            .get("playable")
-                            .unwrap_or(&tiled::PropertyValue::BoolValue(true))
-                        {
-                            tiled::PropertyValue::BoolValue(playable) => *playable,
-                            _ => false,
-                        };
-                        spawn_event.send(SimpleFigureSpawnEvent {
-                            playable,
-                            position: Isometry2::new(
-                                [object.x / rc.scale, y_pixels / rc.scale].into(),
-                                0.0,
-                            ),
-                            ..Default::default()
-                        })
-                    }
+        let map_height_pixels = (tiled_map.height * tiled_map.tile_height) as f32;
+        for layer in tiled_map.layers() {
+            if let tiled::LayerType::Objects(object_layer) = layer.layer_type() {
+                info!("Found object layer {:?}", layer.id());
+                for object in object_layer.objects() {
+                    // In tiled, y increases down
+                    let y_pixels = map_height_pixels - object.y;
+                    map_object_event.send(MapObjectEvent(MapObject {
+                        name: object.name.clone(),
+                        obj_type: object.obj_type.clone(),
+                        shape: object.shape.clone(),
+                        position: Isometry2::new(
+                            [object.x / rc.scale, y_pixels / rc.scale].into(),
+                            0.0,
+                        ),
+                        properties: object.properties.clone(),
+                    }));
                 }
             }
         }
     }
 }
 
+/// Spawn simple figures for map objects of type "simple_figure"
+fn spawn_simple_figures(
+    mut map_object_events: EventReader<MapObjectEvent>,
+    mut spawn_event: EventWriter<SimpleFigureSpawnEvent>,
+) {
+    for MapObjectEvent(object) in map_object_events.iter() {
+        if object.obj_type != "simple_figure" {
+            continue;
+        }
+        if let ObjectShape::Rect { .. } = object.shape {
+            let allegiance = match object.get_string("allegiance", "hostile").as_str() {
+                "friendly" => Allegiance::Friendly,
+                "neutral" => Allegiance::Neutral,
+                _ => Allegiance::Hostile,
+            };
+            spawn_event.send(SimpleFigureSpawnEvent {
+                playable: object.get_bool("playable", true),
+                allegiance,
+                position: object.position,
+                ..Default::default()
+            })
+        }
+    }
+}
+
 #[derive(Component, Default)]
 pub struct WallTag;
 
+/// Parent of all of a map's wall colliders, itself a child of the map entity
+/// that also owns the tile layers
+#[derive(Component)]
+pub struct WallRoot;
+
+/// The tile a wall collider was generated from
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WallSource {
+    pub tile_pos: (u32, u32),
+    pub layer: u16,
+}
+
 #[derive(Bundle, Default)]
 pub struct WallColliderBundle {
     #[bundle]
@@ -244,124 +357,356 @@ pub struct WallColliderBundle {
     #[bundle]
     collider_bundle: ColliderBundle,
     wall_tag: WallTag,
+    source: WallSource,
+}
+
+/// Despawn the wall colliders generated from one tile, e.g. when it changes
+pub fn despawn_walls_at(
+    source: WallSource,
+    walls: &Query<(Entity, &WallSource)>,
+    despawn: &mut EventWriter<DespawnEvent>,
+) {
+    for (entity, wall_source) in walls.iter() {
+        if *wall_source == source {
+            despawn.send(DespawnEvent(entity));
+        }
+    }
+}
+
+/// A wall collider of a tile in the tileset, relative to the tile's top-left
+/// corner, in physics units
+struct WallTemplate {
+    tile_id: u32,
+    half_extents: Vec2,
+    position: Isometry2<f32>,
+}
+
+/// Precompute the wall colliders of every tile in the tileset once, rather
+/// than once per map cell
+fn wall_templates(tiled_map: &tiled::Map, physics_scale: f32) -> HashMap<u32, Vec<WallTemplate>> {
+    let mut templates = HashMap::default();
+    if let Some(tileset) = tiled_map.tilesets().first() {
+        for (id, tile) in tileset.tiles() {
+            if let Some(object_layer_data) = &tile.collision {
+                let tile_templates = object_layer_data
+                    .object_data()
+                    .iter()
+                    .filter_map(|object| {
+                        let x_offset = object.x / physics_scale;
+                        let y_offset = object.y / physics_scale;
+                        match &object.shape {
+                            ObjectShape::Rect { width, height } => {
+                                let physics_width = width / physics_scale;
+                                let physics_height = height / physics_scale;
+                                // The collider position is measured from the center in rapier,
+                                // but in tiled it is from the top-left corner.
+                                // In rapier2d, y increases up, but in tiled, y increases down
+                                let mut position = Isometry2::new(
+                                    [
+                                        physics_width / 2.0 + x_offset,
+                                        -physics_height / 2.0 - y_offset,
+                                    ]
+                                    .into(),
+                                    0.0,
+                                );
+                                // Tiled rotates about the top-left corner
+                                let clockwise_rotation = object.rotation.to_radians();
+                                let counterclockwise_rotation = TAU - clockwise_rotation;
+
+                                // This needs to rotate about the top-left corner
+                                position.append_rotation_wrt_point_mut(
+                                    &UnitComplex::new(counterclockwise_rotation),
+                                    &Point::new(x_offset, -y_offset),
+                                );
+                                Some(WallTemplate {
+                                    tile_id: id,
+                                    // Convert dimensions into half-extants
+                                    half_extents: Vec2::new(
+                                        physics_width / 2.0,
+                                        physics_height / 2.0,
+                                    ),
+                                    position,
+                                })
+                            }
+                            _ => {
+                                warn!("Unsupported object shape: {:?}", object.shape);
+                                None
+                            }
+                        }
+                    })
+                    .collect();
+                templates.insert(id, tile_templates);
+            } else {
+                warn!("No collision data for tile id: {id}");
+            }
+        }
+    }
+    templates
+}
+
+/// A cell of the wall layer whose tile has colliders
+#[derive(Clone, Copy)]
+struct WallCell {
+    tile_pos: (u32, u32),
+    tile_id: u32,
+}
+
+/// Map cells handled together in a single task when preparing walls
+const COLLIDER_CHUNK_SIZE: usize = 1024;
+
+/// Build the collider bundles of every wall cell on the task pool, in the
+/// order of the cells. Building a bundle allocates its shape and works out
+/// where its body and collider go, which is most of the per-cell work, so
+/// only spawning is left for the main thread.
+fn prepare_walls(
+    task_pool: &TaskPool,
+    cells: &[WallCell],
+    templates: &HashMap<u32, Vec<WallTemplate>>,
+    physics_tile_size: Vec2,
+) -> Vec<(WallColliderBundle, u32)> {
+    task_pool
+        .scope(|scope| {
+            for chunk in cells.chunks(COLLIDER_CHUNK_SIZE) {
+                scope.spawn(async move {
+                    let mut walls = Vec::new();
+                    for cell in chunk {
+                        let (x, y) = cell.tile_pos;
+                        // Use top-left corner instead of bottom-left corner
+                        let body_position = Isometry2::new(
+                            [
+                                x as f32 * physics_tile_size.x,
+                                (y + 1) as f32 * physics_tile_size.y,
+                            ]
+                            .into(),
+                            0.0,
+                        );
+                        for template in &templates[&cell.tile_id] {
+                            let bundle = WallColliderBundle {
+                                rigid_body_bundle: RigidBodyBundle {
+                                    body_type: RigidBodyTypeComponent(RigidBodyType::Static),
+                                    position: body_position.into(),
+                                    ..Default::default()
+                                },
+                                collider_bundle: ColliderBundle {
+                                    shape: ColliderShape::cuboid(
+                                        template.half_extents.x,
+                                        template.half_extents.y,
+                                    )
+                                    .into(),
+                                    position: template.position.into(),
+                                    ..Default::default()
+                                },
+                                source: WallSource {
+                                    tile_pos: cell.tile_pos,
+                                    layer: WALL_LAYER_ID,
+                                },
+                                ..Default::default()
+                            };
+                            walls.push((bundle, template.tile_id));
+                        }
+                    }
+                    walls
+                });
+            }
+        })
+        .into_iter()
+        .flatten()
+        .collect()
 }
 
 fn add_colliders(
     rc: Res<RapierConfiguration>,
+    task_pool: Res<ComputeTaskPool>,
     mut commands: Commands,
     tile_query: Query<&Tile>,
     mut map_query: MapQuery,
-    tiled_map_query: Query<&TiledMapComponent, Changed<TiledMapComponent>>,
+    tiled_map_query: Query<(Entity, &TiledMapComponent), Changed<TiledMapComponent>>,
+    mut map_ready_event: EventWriter<MapReadyEvent>,
 ) {
-    for TiledMapComponent(tiled_map) in tiled_map_query.iter() {
-        let mut collider_spawners = std::collections::HashMap::new();
-        if let Some(tileset) = tiled_map.tilesets().first() {
-            for (id, tile) in tileset.tiles() {
-                if let Some(object_layer_data) = &tile.collision {
-                    // Clone these so we can just move them into the closure
-                    let object_layer_data = object_layer_data.clone();
-                    let physics_scale = rc.scale;
-                    collider_spawners.insert(
-                        id,
-                        move |commands: &mut Commands, column: u32, row: u32| -> Vec<Entity> {
-                            object_layer_data
-                                .object_data()
-                                .iter()
-                                .filter_map(|object| {
-                                    let physics_tile_height =
-                                        tiled_map.tile_height as f32 / physics_scale;
-
-                                    let x = (column * tiled_map.tile_width) as f32 / physics_scale;
-                                    let y = (row * tiled_map.tile_height) as f32 / physics_scale;
-                                    let x_offset = object.x / physics_scale;
-                                    let y_offset = object.y / physics_scale;
-                                    match &object.shape {
-                                        ObjectShape::Rect { width, height } => {
-                                            let physics_width = width / physics_scale;
-                                            let physics_height = height / physics_scale;
-                                            // The collider position is measured from the center in rapier,
-                                            // but in tiled it is from the top-left corner.
-                                            // In rapier2d, y increases up, but in tiled, y increases down
-                                            let mut collider_position = Isometry2::new(
-                                                [
-                                                    physics_width / 2.0 + x_offset,
-                                                    -physics_height / 2.0 - y_offset,
-                                                ]
-                                                .into(),
-                                                0.0,
-                                            );
-                                            // Tiled rotates about the top-left corner
-                                            let clockwise_rotation = object.rotation.to_radians();
-                                            let counterclockwise_rotation =
-                                                TAU - clockwise_rotation;
-
-                                            // This needs to rotate about the top-left corner
-                                            collider_position.append_rotation_wrt_point_mut(
-                                                &UnitComplex::new(counterclockwise_rotation),
-                                                &Point::new(x_offset, -y_offset),
-                                            );
-                                            Some(
-                                                commands
-                                                    .spawn_bundle(WallColliderBundle {
-                                                        rigid_body_bundle: RigidBodyBundle {
-                                                            body_type: RigidBodyTypeComponent(
-                                                                RigidBodyType::Static,
-                                                            ),
-                                                            // Use top-left corner instead of bottom-left corner
-                                                            position: Isometry2::new(
-                                                                [x, y + physics_tile_height].into(),
-                                                                0.0,
-                                                            )
-                                                            .into(),
-                                                            ..Default::default()
-                                                        },
-                                                        collider_bundle: ColliderBundle {
-                                                            // Convert dimensions into half-extants
-                                                            shape: ColliderShape::cuboid(
-                                                                physics_width / 2.0,
-                                                                physics_height / 2.0,
-                                                            )
-                                                            .into(),
-                                                            position: collider_position.into(),
-                                                            ..Default::default()
-                                                        },
-                                                        ..Default::default()
-                                                    })
-                                                    .insert(ColliderDebugRender::with_id(
-                                                        id as usize,
-                                                    ))
-                                                    .insert(ColliderPositionSync::Discrete)
-                                                    .id(),
-                                            )
-                                        }
-                                        _ => {
-                                            warn!("Unsupported object shape: {:?}", object.shape);
-                                            None
-                                        }
-                                    }
-                                })
-                                .collect()
-                        },
-                    );
-                } else {
-                    warn!("No collision data for tile id: {id}");
-                }
-            }
-        }
+    for (map_entity, TiledMapComponent(tiled_map)) in tiled_map_query.iter() {
+        let started = Instant::now();
+        let physics_scale = rc.scale;
+        let templates = wall_templates(tiled_map, physics_scale);
 
+        // Tile lookups need the ECS, so find the cells with colliders first
+        let mut cells = Vec::new();
         for x in 0..tiled_map.width {
             for y in 0..tiled_map.height {
-                if let Ok(tile_entity) = map_query.get_tile_entity(TilePos(x, y), MAP_ID, 1) {
-                    if let Ok(tile) = tile_query.get(tile_entity) {
-                        if let Some(spawner) = collider_spawners.get(&(tile.texture_index as u32)) {
-                            let object_entities = spawner(&mut commands, x, y);
-                            commands
-                                .entity(tile_entity)
-                                .push_children(object_entities.as_slice());
-                        }
+                if let Some(tile) = map_query
+                    .get_tile_entity(TilePos(x, y), MAP_ID, WALL_LAYER_ID)
+                    .ok()
+                    .and_then(|tile_entity| tile_query.get(tile_entity).ok())
+                {
+                    let tile_id = tile.texture_index as u32;
+                    if templates.contains_key(&tile_id) {
+                        cells.push(WallCell {
+                            tile_pos: (x, y),
+                            tile_id,
+                        });
                     }
                 }
             }
         }
+        let gathered = Instant::now();
+
+        let physics_tile_size =
+            Vec2::new(tiled_map.tile_width as f32, tiled_map.tile_height as f32) / physics_scale;
+        let prepared = prepare_walls(&task_pool, &cells, &templates, physics_tile_size);
+        let prepared_at = Instant::now();
+
+        // Spawn every wall under a single root, so they can be found and
+        // cleaned up together with the map
+        let wall_root = commands
+            .spawn()
+            .insert(WallRoot)
+            .insert(Transform::identity())
+            .insert(GlobalTransform::identity())
+            .id();
+        commands.entity(map_entity).push_children(&[wall_root]);
+        let walls: Vec<Entity> = prepared
+            .into_iter()
+            .map(|(bundle, tile_id)| {
+                commands
+                    .spawn_bundle(bundle)
+                    .insert(ColliderDebugRender::with_id(tile_id as usize))
+                    .insert(ColliderPositionSync::Discrete)
+                    .id()
+            })
+            .collect();
+        commands.entity(wall_root).push_children(&walls);
+
+        info!(
+            "Map layers and {} wall colliders spawned in {:?}: {:?} finding {} cells, \
+             {:?} preparing, {:?} spawning",
+            walls.len(),
+            started.elapsed(),
+            gathered - started,
+            cells.len(),
+            prepared_at - gathered,
+            prepared_at.elapsed(),
+        );
+        commands.entity(map_entity).insert(MapReady);
+        map_ready_event.send(MapReadyEvent(map_entity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::DespawnPlugin;
+    use bevy::tasks::TaskPoolBuilder;
+
+    const TARGET: WallSource = WallSource {
+        tile_pos: (1, 1),
+        layer: WALL_LAYER_ID,
+    };
+
+    fn despawn_target(walls: Query<(Entity, &WallSource)>, mut despawn: EventWriter<DespawnEvent>) {
+        despawn_walls_at(TARGET, &walls, &mut despawn);
+    }
+
+    #[test]
+    fn despawn_walls_at_only_removes_that_tile() {
+        let mut app = App::new();
+        app.add_plugin(DespawnPlugin).add_system(despawn_target);
+        // A tile can have several colliders
+        let target_walls = [
+            app.world.spawn().insert(TARGET).id(),
+            app.world.spawn().insert(TARGET).id(),
+        ];
+        let neighbour = app
+            .world
+            .spawn()
+            .insert(WallSource {
+                tile_pos: (2, 1),
+                layer: WALL_LAYER_ID,
+            })
+            .id();
+        let other_layer = app
+            .world
+            .spawn()
+            .insert(WallSource {
+                tile_pos: (1, 1),
+                layer: WALL_LAYER_ID + 1,
+            })
+            .id();
+
+        app.update();
+
+        for wall in target_walls {
+            assert!(app.world.get_entity(wall).is_none());
+        }
+        assert!(app.world.get_entity(neighbour).is_some());
+        assert!(app.world.get_entity(other_layer).is_some());
+    }
+
+    /// Two colliders per tile on every cell of a 256×256 map, in 16 px tiles
+    fn collision_heavy_map() -> (Vec<WallCell>, HashMap<u32, Vec<WallTemplate>>) {
+        let mut templates = HashMap::default();
+        templates.insert(
+            7,
+            vec![
+                WallTemplate {
+                    tile_id: 7,
+                    half_extents: Vec2::new(0.8, 0.4),
+                    position: Isometry2::new([0.8, -0.4].into(), 0.0),
+                },
+                WallTemplate {
+                    tile_id: 7,
+                    half_extents: Vec2::new(0.4, 0.8),
+                    position: Isometry2::new([0.4, -0.8].into(), 0.0),
+                },
+            ],
+        );
+        let cells = (0..256)
+            .flat_map(|x| {
+                (0..256).map(move |y| WallCell {
+                    tile_pos: (x, y),
+                    tile_id: 7,
+                })
+            })
+            .collect();
+        (cells, templates)
+    }
+
+    #[test]
+    fn walls_of_a_256_by_256_map_are_prepared_in_parallel() {
+        let (cells, templates) = collision_heavy_map();
+        let tile_size = Vec2::splat(1.6);
+
+        let single_thread = TaskPoolBuilder::new().num_threads(1).build();
+        let started = Instant::now();
+        let sequential = prepare_walls(&single_thread, &cells, &templates, tile_size);
+        let sequential_time = started.elapsed();
+
+        let pool = TaskPoolBuilder::new().build();
+        let started = Instant::now();
+        let parallel = prepare_walls(&pool, &cells, &templates, tile_size);
+        let parallel_time = started.elapsed();
+        println!(
+            "Prepared {} walls in {:?} on one thread, {:?} on {} threads",
+            parallel.len(),
+            sequential_time,
+            parallel_time,
+            pool.thread_num()
+        );
+
+        assert_eq!(parallel.len(), 256 * 256 * 2);
+        assert_eq!(sequential.len(), parallel.len());
+        // Generous, so it only fails if preparing has become pathological
+        assert!(parallel_time.as_secs_f32() < 5.0);
+
+        // Same walls, in cell order, no matter how the work was split up
+        for (a, b) in sequential.iter().zip(parallel.iter()) {
+            assert_eq!(a.0.source, b.0.source);
+        }
+        let (wall, tile_id) = &parallel[2 * (3 * 256 + 5) + 1];
+        assert_eq!(*tile_id, 7);
+        assert_eq!(wall.source.tile_pos, (3, 5));
+        let body: Vec2 = wall.rigid_body_bundle.position.position.translation.into();
+        assert!((body - Vec2::new(3.0 * 1.6, 6.0 * 1.6)).length() < 1e-4);
+        let cuboid = wall.collider_bundle.shape.as_cuboid().unwrap();
+        assert_eq!(cuboid.half_extents.x, 0.4);
+        assert_eq!(cuboid.half_extents.y, 0.8);
     }
 }
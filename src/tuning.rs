@@ -0,0 +1,380 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use bevy::asset::FileAssetIo;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::health::Health;
+use crate::input::{MoveSpeed, PlayerTag};
+
+/// Loads gameplay numbers from assets/tuning.ron, so balancing doesn't need a
+/// recompile. Debug builds reload the file whenever it changes.
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Tuning::load()).add_system(apply_tuning);
+        if cfg!(debug_assertions) {
+            app.add_system(reload_tuning);
+        }
+    }
+}
+
+/// Gameplay numbers, each section defaulting to the built-in values when
+/// missing from the file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    pub movement: MovementTuning,
+    pub combat: CombatTuning,
+    pub ai: AiTuning,
+    pub camera: CameraTuning,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MovementTuning {
+    pub player_speed: f32, // m/s
+}
+
+impl Default for MovementTuning {
+    fn default() -> Self {
+        MovementTuning { player_speed: 5.0 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CombatTuning {
+    pub player_max_health: i32,
+    /// Minimum time between player shots
+    pub shoot_cooldown: f32, // seconds
+    pub shot_speed: f32, // m/s
+    /// Damage a ball deals on contact
+    pub ball_damage: i32,
+}
+
+impl Default for CombatTuning {
+    fn default() -> Self {
+        CombatTuning {
+            player_max_health: 10,
+            shoot_cooldown: 0.2,
+            shot_speed: 10.0,
+            ball_damage: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AiTuning {
+    /// Time between hostile path replans on Normal difficulty, before the
+    /// difficulty curve
+    pub replan_interval: f32, // seconds
+    /// How far behind the player friendlies trail
+    pub follow_distance: f32, // m
+    /// Friendlies shoot at hostiles within this range
+    pub friendly_sight_range: f32, // m
+    pub friendly_shoot_interval: f32, // seconds
+}
+
+impl Default for AiTuning {
+    fn default() -> Self {
+        AiTuning {
+            replan_interval: 0.5,
+            follow_distance: 1.5,
+            friendly_sight_range: 6.0,
+            friendly_shoot_interval: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraTuning {
+    /// How far the target may move from the center before the camera follows
+    pub dead_zone: f32, // px
+}
+
+impl Default for CameraTuning {
+    fn default() -> Self {
+        CameraTuning { dead_zone: 32.0 }
+    }
+}
+
+/// Check that a number is finite and above min, or at least min if zero is
+/// allowed
+fn check(name: &str, value: f32, min: f32, allow_min: bool) -> Result<(), String> {
+    let in_range = if allow_min { value >= min } else { value > min };
+    if value.is_finite() && in_range {
+        Ok(())
+    } else {
+        Err(format!("{} can't be {}", name, value))
+    }
+}
+
+impl MovementTuning {
+    fn validate(&self) -> Result<(), String> {
+        check("player_speed", self.player_speed, 0.0, false)
+    }
+}
+
+impl CombatTuning {
+    fn validate(&self) -> Result<(), String> {
+        if self.player_max_health < 1 {
+            return Err(format!(
+                "player_max_health can't be {}",
+                self.player_max_health
+            ));
+        }
+        if self.ball_damage < 0 {
+            return Err(format!("ball_damage can't be {}", self.ball_damage));
+        }
+        check("shoot_cooldown", self.shoot_cooldown, 0.0, true)?;
+        check("shot_speed", self.shot_speed, 0.0, false)
+    }
+}
+
+impl AiTuning {
+    fn validate(&self) -> Result<(), String> {
+        check("replan_interval", self.replan_interval, 0.0, false)?;
+        check("follow_distance", self.follow_distance, 0.0, true)?;
+        check("friendly_sight_range", self.friendly_sight_range, 0.0, true)?;
+        check(
+            "friendly_shoot_interval",
+            self.friendly_shoot_interval,
+            0.0,
+            false,
+        )
+    }
+}
+
+impl CameraTuning {
+    fn validate(&self) -> Result<(), String> {
+        check("dead_zone", self.dead_zone, 0.0, true)
+    }
+}
+
+/// Keep value if it is valid, otherwise warn and use fallback instead
+fn valid_or<T: Clone>(section: &str, value: T, result: Result<(), String>, fallback: &T) -> T {
+    match result {
+        Ok(()) => value,
+        Err(err) => {
+            warn!("Ignoring {} tuning: {}", section, err);
+            fallback.clone()
+        }
+    }
+}
+
+impl Tuning {
+    /// tuning.ron in the assets directory
+    pub fn path() -> PathBuf {
+        FileAssetIo::get_root_path()
+            .join("assets")
+            .join("tuning.ron")
+    }
+
+    /// Load the tuning file, falling back to the built-in values if there is
+    /// none or it can't be read
+    pub fn load() -> Self {
+        Tuning::load_from(&Tuning::path(), &Tuning::default())
+    }
+
+    /// Load tuning from path, falling back to fallback if there is no file
+    /// there or it can't be read, and to fallback's section for any section
+    /// with invalid values
+    pub fn load_from(path: &Path, fallback: &Tuning) -> Self {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => {
+                info!("No tuning at {}, using defaults", path.display());
+                return fallback.clone();
+            }
+        };
+        match ron::from_str::<Tuning>(&text) {
+            Ok(tuning) => {
+                info!("Loaded tuning from {}", path.display());
+                tuning.validate(fallback)
+            }
+            Err(err) => {
+                warn!("Ignoring tuning at {}: {}", path.display(), err);
+                fallback.clone()
+            }
+        }
+    }
+
+    /// Replace each section holding values the game can't use, like negative
+    /// or NaN durations, with the same section of fallback
+    pub fn validate(self, fallback: &Tuning) -> Tuning {
+        let movement = self.movement.validate();
+        let combat = self.combat.validate();
+        let ai = self.ai.validate();
+        let camera = self.camera.validate();
+        Tuning {
+            movement: valid_or("movement", self.movement, movement, &fallback.movement),
+            combat: valid_or("combat", self.combat, combat, &fallback.combat),
+            ai: valid_or("ai", self.ai, ai, &fallback.ai),
+            camera: valid_or("camera", self.camera, camera, &fallback.camera),
+        }
+    }
+}
+
+/// How often to check the tuning file for changes
+const RELOAD_INTERVAL: f32 = 1.0; // seconds
+
+/// Poll the tuning file's modification time, and reload it when it changes
+fn reload_tuning(
+    time: Res<Time>,
+    mut tuning: ResMut<Tuning>,
+    mut timer: Local<Option<Timer>>,
+    mut last_modified: Local<Option<SystemTime>>,
+) {
+    let timer = timer.get_or_insert_with(|| Timer::from_seconds(RELOAD_INTERVAL, true));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = match fs::metadata(Tuning::path()).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        // Keep the current values while the file is missing
+        Err(_) => return,
+    };
+    // The first check only records the time, since the file was just loaded
+    let previous = last_modified.replace(modified);
+    if previous.is_none() || previous == Some(modified) {
+        return;
+    }
+
+    // Bad values keep what is running rather than going back to defaults
+    let reloaded = Tuning::load_from(&Tuning::path(), &*tuning);
+    if reloaded != *tuning {
+        info!("Tuning changed, applying");
+        *tuning = reloaded;
+    }
+}
+
+/// Push tuning changes into the components built from it
+fn apply_tuning(
+    tuning: Res<Tuning>,
+    mut players: Query<(&mut MoveSpeed, Option<&mut Health>), With<PlayerTag>>,
+) {
+    if !tuning.is_changed() {
+        return;
+    }
+    for (mut move_speed, health) in players.iter_mut() {
+        move_speed.0 = tuning.movement.player_speed;
+        if let Some(mut health) = health {
+            // Keep the damage taken so far, without killing anyone
+            let max = tuning.combat.player_max_health;
+            health.current = (health.current + max - health.max).min(max).max(1);
+            health.max = max;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write text to a tuning file of its own in the temp directory
+    fn tuning_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("bevy_sandbox_{}.ron", name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn tuning_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Tuning>().add_system(apply_tuning);
+        app
+    }
+
+    #[test]
+    fn modified_file_changes_move_speed() {
+        let path = tuning_file(
+            "modified",
+            "(movement: (player_speed: 7.5), combat: (player_max_health: 12))",
+        );
+        let tuning = Tuning::load_from(&path, &Tuning::default());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tuning.movement.player_speed, 7.5);
+        // Missing fields keep their defaults
+        assert_eq!(tuning.combat.shot_speed, CombatTuning::default().shot_speed);
+        assert_eq!(tuning.ai, AiTuning::default());
+
+        let mut app = tuning_app();
+        let player = app
+            .world
+            .spawn()
+            .insert(PlayerTag)
+            .insert(MoveSpeed(5.0))
+            .insert(Health {
+                max: 10,
+                current: 7,
+            })
+            .id();
+        app.update();
+
+        app.insert_resource(tuning);
+        app.update();
+        assert_eq!(app.world.get::<MoveSpeed>(player).unwrap().0, 7.5);
+        let health = app.world.get::<Health>(player).unwrap();
+        assert_eq!((health.max, health.current), (12, 9));
+    }
+
+    #[test]
+    fn missing_file_falls_back_to_defaults() {
+        let path = std::env::temp_dir().join("bevy_sandbox_missing_tuning.ron");
+        let _ = fs::remove_file(&path);
+        assert_eq!(
+            Tuning::load_from(&path, &Tuning::default()),
+            Tuning::default()
+        );
+    }
+
+    #[test]
+    fn unreadable_file_falls_back_to_defaults() {
+        let path = tuning_file("broken", "(movement: (player_speed: \"fast\"))");
+        let tuning = Tuning::load_from(&path, &Tuning::default());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tuning, Tuning::default());
+    }
+
+    #[test]
+    fn invalid_section_falls_back_to_its_defaults() {
+        let path = tuning_file(
+            "invalid",
+            "(movement: (player_speed: 7.5), ai: (replan_interval: -1.0, follow_distance: 3.0))",
+        );
+        let tuning = Tuning::load_from(&path, &Tuning::default());
+        fs::remove_file(&path).unwrap();
+        // The whole ai section is dropped, the rest is kept
+        assert_eq!(tuning.ai, AiTuning::default());
+        assert_eq!(tuning.movement.player_speed, 7.5);
+    }
+
+    #[test]
+    fn invalid_section_keeps_the_fallback_section() {
+        let fallback = Tuning {
+            combat: CombatTuning {
+                shoot_cooldown: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let tuning = Tuning {
+            combat: CombatTuning {
+                shoot_cooldown: f32::NAN,
+                ..Default::default()
+            },
+            camera: CameraTuning { dead_zone: 10.0 },
+            ..Default::default()
+        };
+
+        let tuning = tuning.validate(&fallback);
+        assert_eq!(tuning.combat, fallback.combat);
+        assert_eq!(tuning.camera.dead_zone, 10.0);
+    }
+}